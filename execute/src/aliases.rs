@@ -0,0 +1,175 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+
+// One alias per line, `#` starts a comment:
+//
+//   fetch-work = -w 8 -t 60 -c work.conf -- git fetch --all --prune
+//
+// Words are split like a shell would, without any expansion.
+pub fn aliases_path(home: &str) -> PathBuf {
+    config::config_path("execute-aliases.conf", home)
+}
+
+pub struct Alias {
+    pub name: String,
+    // as written in the file, for --list-aliases
+    pub definition: String,
+    pub args: Vec<String>,
+}
+
+pub fn read(path: &Path) -> Result<Vec<Alias>, String> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("cannot read aliases {:?}: {}", path, e)),
+    };
+
+    let mut aliases = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((name, definition)) = line.split_once('=') else {
+            return Err(format!(
+                "{}:{}: expected `<name> = <options> -- <command>`",
+                path.display(),
+                number + 1
+            ));
+        };
+        let args = split_words(definition.trim())
+            .map_err(|e| format!("{}:{}: {}", path.display(), number + 1, e))?;
+        aliases.push(Alias {
+            name: name.trim().to_string(),
+            definition: definition.trim().to_string(),
+            args,
+        });
+    }
+    Ok(aliases)
+}
+
+// `-c 'my repos.conf' -- grep "a b"` -> [-c, my repos.conf, --, grep, a b]
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated '".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => word.push(c),
+                            None => return Err("unterminated \"".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated \"".to_string()),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                if let Some(c) = chars.next() {
+                    word.push(c);
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+// splits at the first `--`, the second part keeps it
+fn split_command<T: PartialEq<str>>(mut args: Vec<T>) -> (Vec<T>, Vec<T>) {
+    match args.iter().position(|arg| arg == "--") {
+        Some(i) => {
+            let command = args.split_off(i);
+            (args, command)
+        }
+        None => (args, Vec::new()),
+    }
+}
+
+// `@name [options] [-- <command>]` -> the alias' options, then ours so they
+// win, then our command or else the alias' command
+pub fn expand(args: Vec<OsString>, path: &Path) -> Result<Vec<OsString>, String> {
+    let Some(name) = args
+        .first()
+        .and_then(|first| first.to_str())
+        .and_then(|first| first.strip_prefix('@'))
+    else {
+        return Ok(args);
+    };
+
+    let aliases = read(path)?;
+    let Some(alias) = aliases.iter().find(|alias| alias.name == name) else {
+        return Err(format!(
+            "unknown alias @{} (in {:?}), see --list-aliases",
+            name, path
+        ));
+    };
+
+    let alias_args: Vec<OsString> = alias.args.iter().map(OsString::from).collect();
+    let (alias_options, alias_command) = split_command(alias_args);
+    let (options, command) = split_command(args[1..].to_vec());
+
+    let mut expanded = alias_options;
+    expanded.extend(options);
+    if command.is_empty() {
+        expanded.extend(alias_command);
+    } else {
+        expanded.extend(command);
+    }
+    Ok(expanded)
+}
+
+pub fn print_list(path: &Path) -> Result<(), String> {
+    let aliases = read(path)?;
+    if aliases.is_empty() {
+        println!("no aliases in {}", path.display());
+        return Ok(());
+    }
+    let width = aliases
+        .iter()
+        .map(|alias| alias.name.len())
+        .max()
+        .unwrap_or(0);
+    for alias in &aliases {
+        println!(
+            "@{:width$}  {}",
+            alias.name,
+            alias.definition,
+            width = width
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use std::mem::size_of;
+
+// what execve(2) needs per argument: the string, its NUL and the pointer to it
+fn arg_bytes(arg: &str) -> usize {
+    arg.len() + 1 + size_of::<usize>()
+}
+
+// ARG_MAX minus what the environment already takes and some headroom,
+// this is what xargs does as well
+pub fn default_max_args_bytes() -> usize {
+    // SAFETY: sysconf has no memory safety requirements
+    let arg_max = unsafe { libc::sysconf(libc::_SC_ARG_MAX) };
+    let arg_max = if arg_max > 0 {
+        arg_max as usize
+    } else {
+        128 * 1024
+    };
+    let env_bytes: usize = std::env::vars_os()
+        .map(|(key, value)| key.len() + value.len() + 2 + size_of::<usize>())
+        .sum();
+    arg_max
+        .saturating_sub(env_bytes)
+        .saturating_sub(4096)
+        .max(4096)
+}
+
+// Packs paths into batches of at most `batch_size` paths whose arguments,
+// together with the command itself, stay below `max_bytes`.
+// A single path that is too long still gets a batch of its own.
+pub struct Batcher {
+    batch_size: usize,
+    max_bytes: usize,
+    command_bytes: usize,
+    batch: Vec<String>,
+    batch_bytes: usize,
+}
+
+impl Batcher {
+    pub fn new(command: &[String], batch_size: usize, max_bytes: usize) -> Batcher {
+        let command_bytes: usize = command.iter().map(|arg| arg_bytes(arg)).sum();
+        Batcher {
+            batch_size,
+            max_bytes,
+            command_bytes,
+            batch: Vec::new(),
+            batch_bytes: command_bytes,
+        }
+    }
+
+    // returns the previous batch once `path` does not fit anymore
+    pub fn push(&mut self, path: String) -> Option<Vec<String>> {
+        let bytes = arg_bytes(&path);
+        let mut full = None;
+        if !self.batch.is_empty()
+            && (self.batch.len() >= self.batch_size || self.batch_bytes + bytes > self.max_bytes)
+        {
+            full = Some(std::mem::take(&mut self.batch));
+            self.batch_bytes = self.command_bytes;
+        }
+        self.batch_bytes += bytes;
+        self.batch.push(path);
+        full
+    }
+
+    // the batch so far, for a path that must not join it
+    pub fn take(&mut self) -> Option<Vec<String>> {
+        if self.batch.is_empty() {
+            return None;
+        }
+        self.batch_bytes = self.command_bytes;
+        Some(std::mem::take(&mut self.batch))
+    }
+
+    pub fn finish(mut self) -> Option<Vec<String>> {
+        self.take()
+    }
+}
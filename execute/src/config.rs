@@ -0,0 +1,415 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use brace_expand::brace_expand;
+use globby::glob;
+use shellexpand::full;
+
+use crate::{debug, log_err};
+
+pub fn config_path(config_filename: &str, home: &str) -> PathBuf {
+    let mut config_path = PathBuf::from(config_filename);
+    if !config_path.is_absolute() {
+        debug!("config_path: {:?} is not absolute.", config_path);
+        config_path =
+            PathBuf::from(format!("{}/{}/{}", home, ".config/personal", config_filename).as_str());
+        debug!("updated config_path: {:?}", config_path);
+    }
+    config_path
+}
+
+// One config line -> the paths it stands for. Globs are walked lazily.
+fn expand_line(line: String) -> Box<dyn Iterator<Item = String> + Send> {
+    let shell_expanded: String;
+    if let Ok(sh_expanded) = full(&line) {
+        shell_expanded = sh_expanded.into_owned();
+    } else {
+        shell_expanded = line;
+    }
+    debug!("shell_expanded: {}", shell_expanded);
+
+    if !shell_expanded.contains("*") && !shell_expanded.contains("{") {
+        return Box::new(std::iter::once(shell_expanded));
+    }
+
+    let brace_expanded = brace_expand(&shell_expanded);
+    debug!("brace_expanded: {:?}", brace_expanded);
+
+    Box::new(brace_expanded.into_iter().flat_map(|expanded| {
+        glob(&expanded)
+            .expect("Glob failed")
+            .map(|item| item.expect("Error on path in glob").display().to_string())
+    }))
+}
+
+// `key=value` words a config line may end with, the rest is the path
+fn is_option(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((key, _)) => {
+            matches!(key, "after" | "timeout" | "cwd")
+                || key
+                    .strip_prefix("env.")
+                    .is_some_and(|name| !name.is_empty())
+        }
+        None => false,
+    }
+}
+
+// `~/Repos/mono timeout=30s cwd=services` -> (`~/Repos/mono`, [timeout=30s, cwd=services]),
+// a line of nothing but options has no path
+fn split_options(line: &str) -> (&str, Vec<(&str, &str)>) {
+    let mut path = line.trim();
+    let mut options = Vec::new();
+    while !path.is_empty() {
+        let (rest, word) = path.rsplit_once(char::is_whitespace).unwrap_or(("", path));
+        if !is_option(word) {
+            break;
+        }
+        options.extend(word.split_once('='));
+        path = rest.trim_end();
+    }
+    options.reverse();
+    (path, options)
+}
+
+fn shell_expanded(value: &str) -> String {
+    match full(value) {
+        Ok(expanded) => expanded.into_owned(),
+        Err(_) => value.to_string(),
+    }
+}
+
+// What a config line may change for its paths, unset is the command line's.
+#[derive(Clone, Default, PartialEq)]
+pub struct EntryOptions {
+    pub timeout: Option<Duration>,
+    // relative to the path
+    pub cwd: Option<String>,
+    pub env: Vec<(String, String)>,
+}
+
+impl EntryOptions {
+    // `other` wins, env variables are merged
+    fn merged(&self, other: &EntryOptions) -> EntryOptions {
+        let mut env = self.env.clone();
+        for (name, value) in &other.env {
+            env.retain(|(existing, _)| existing != name);
+            env.push((name.clone(), value.clone()));
+        }
+        EntryOptions {
+            timeout: other.timeout.or(self.timeout),
+            cwd: other.cwd.clone().or(self.cwd.clone()),
+            env,
+        }
+    }
+}
+
+// A path of the config with what it has to run after and its options.
+#[derive(Clone)]
+pub struct Entry {
+    pub path: String,
+    // as written, paths or names of other entries
+    pub after: Vec<String>,
+    pub options: Arc<EntryOptions>,
+}
+
+struct Line {
+    path: String,
+    after: Vec<String>,
+    options: EntryOptions,
+}
+
+fn parse_line(line: &str) -> Result<Line, String> {
+    let (path, words) = split_options(line);
+    let mut parsed = Line {
+        path: path.to_string(),
+        after: Vec::new(),
+        options: EntryOptions::default(),
+    };
+    for (key, value) in words {
+        match key {
+            "after" => parsed.after.extend(
+                value
+                    .split(',')
+                    .filter(|dependency| !dependency.is_empty())
+                    .map(shell_expanded),
+            ),
+            "timeout" => parsed.options.timeout = Some(crate::parse_duration(value)?),
+            "cwd" => parsed.options.cwd = Some(shell_expanded(value)),
+            _ => {
+                let name = key.trim_start_matches("env.");
+                parsed.options.env.retain(|(existing, _)| existing != name);
+                parsed
+                    .options
+                    .env
+                    .push((name.to_string(), shell_expanded(value)));
+            }
+        }
+    }
+    Ok(parsed)
+}
+
+// Entries of a config as they are read, the config may be a pipe that is
+// still being written to (`-c <(find ...)`). A line of only options is the
+// default for the lines below it.
+fn entries(
+    config_path: &PathBuf,
+) -> io::Result<impl Iterator<Item = Result<Entry, String>> + Send + use<>> {
+    let reader = BufReader::new(File::open(config_path)?);
+    let config = config_path.display().to_string();
+    Ok(reader
+        .split(b'\n')
+        .enumerate()
+        // a line that is not UTF-8 is reported, reading stops at the first I/O error
+        .scan(false, |failed, (number, line)| {
+            if *failed {
+                return None;
+            }
+            let line = match line {
+                Ok(mut line) => {
+                    if line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+                    String::from_utf8(line).map_err(|_| "not valid UTF-8".to_string())
+                }
+                Err(e) => {
+                    *failed = true;
+                    Err(format!("cannot read on: {}", e))
+                }
+            };
+            Some((number, line))
+        })
+        .filter(|(_, line)| {
+            !matches!(line, Ok(line) if line.starts_with("#") || line.trim().is_empty())
+        })
+        .scan(
+            Arc::new(EntryOptions::default()),
+            move |defaults, (number, line)| {
+                let entries: Box<dyn Iterator<Item = Result<Entry, String>> + Send> =
+                    match line.and_then(|line| parse_line(&line)) {
+                        Err(e) => Box::new(std::iter::once(Err(format!(
+                            "{}:{}: {}",
+                            config,
+                            number + 1,
+                            e
+                        )))),
+                        Ok(parsed) if parsed.path.is_empty() && !parsed.after.is_empty() => {
+                            Box::new(std::iter::once(Err(format!(
+                                "{}:{}: after= needs a path",
+                                config,
+                                number + 1
+                            ))))
+                        }
+                        Ok(parsed) if parsed.path.is_empty() => {
+                            *defaults = Arc::new(defaults.merged(&parsed.options));
+                            Box::new(std::iter::empty())
+                        }
+                        Ok(parsed) => {
+                            let options = Arc::new(defaults.merged(&parsed.options));
+                            let after = parsed.after;
+                            Box::new(expand_line(parsed.path).map(move |path| {
+                                Ok(Entry {
+                                    path,
+                                    after: after.clone(),
+                                    options: options.clone(),
+                                })
+                            }))
+                        }
+                    };
+                Some(entries)
+            },
+        )
+        .flatten())
+}
+
+// Lines that cannot be read or have invalid options are reported and left
+// out, the tasks of the lines before them may run already.
+pub fn stream_entries(
+    config_path: &PathBuf,
+) -> io::Result<impl Iterator<Item = Entry> + Send + use<>> {
+    Ok(entries(config_path)?.filter_map(|entry| match entry {
+        Ok(entry) => Some(entry),
+        Err(e) => {
+            log_err!("{}, line skipped", e);
+            None
+        }
+    }))
+}
+
+// All at once (--deps, --schedule history, --pick and --watch), bad lines are
+// left out the same way.
+pub fn get_entries(config_path: &PathBuf) -> Result<Vec<Entry>, String> {
+    let entries: Vec<Entry> = stream_entries(config_path)
+        .map_err(|e| format!("cannot read config {:?}: {}", config_path, e))?
+        .collect();
+    debug!(
+        "paths: {:?}",
+        entries.iter().map(|entry| &entry.path).collect::<Vec<_>>()
+    );
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a config file of `content` that is gone after `f`
+    fn with_config<T>(name: &str, content: impl AsRef<[u8]>, f: impl FnOnce(&PathBuf) -> T) -> T {
+        let path = std::env::temp_dir().join(format!(
+            "execute-config-{}-{}.conf",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, content).unwrap();
+        let result = f(&path);
+        let _ = std::fs::remove_file(&path);
+        result
+    }
+
+    #[test]
+    fn options_are_known_keys() {
+        assert!(is_option("timeout=30s"));
+        assert!(is_option("cwd=services"));
+        assert!(is_option("after=a,b"));
+        assert!(is_option("env.RUST_LOG=debug"));
+        assert!(!is_option("env.=debug"));
+        assert!(!is_option("name=value"));
+        assert!(!is_option("timeout"));
+    }
+
+    #[test]
+    fn path_with_spaces() {
+        let (path, options) = split_options("/tmp/my repo  timeout=30s cwd=sub dir");
+        assert_eq!(path, "/tmp/my repo  timeout=30s cwd=sub dir");
+        assert!(options.is_empty());
+
+        let (path, options) = split_options("  /tmp/my repo timeout=30s cwd=sub  ");
+        assert_eq!(path, "/tmp/my repo");
+        assert_eq!(options, vec![("timeout", "30s"), ("cwd", "sub")]);
+    }
+
+    #[test]
+    fn path_with_equals_sign() {
+        let (path, options) = split_options("/tmp/key=value");
+        assert_eq!(path, "/tmp/key=value");
+        assert!(options.is_empty());
+
+        let (path, options) = split_options("/tmp/a=b/timeout=1s timeout=2s");
+        assert_eq!(path, "/tmp/a=b/timeout=1s");
+        assert_eq!(options, vec![("timeout", "2s")]);
+
+        let parsed = parse_line("/tmp/repo env.FLAGS=-O2=fast").unwrap();
+        assert_eq!(parsed.path, "/tmp/repo");
+        assert_eq!(
+            parsed.options.env,
+            vec![("FLAGS".to_string(), "-O2=fast".to_string())]
+        );
+    }
+
+    #[test]
+    fn options_only_line() {
+        let parsed = parse_line("timeout=5s env.CI=1").unwrap();
+        assert_eq!(parsed.path, "");
+        assert_eq!(parsed.options.timeout, Some(Duration::from_secs(5)));
+        assert_eq!(
+            parsed.options.env,
+            vec![("CI".to_string(), "1".to_string())]
+        );
+    }
+
+    #[test]
+    fn defaults_apply_to_the_lines_below() {
+        let config = "/tmp/first\ntimeout=5s cwd=sub\n/tmp/second\n/tmp/third timeout=1s\n";
+        let entries = with_config("defaults", config, get_entries).unwrap();
+        let timeouts: Vec<_> = entries.iter().map(|entry| entry.options.timeout).collect();
+        assert_eq!(
+            timeouts,
+            vec![
+                None,
+                Some(Duration::from_secs(5)),
+                Some(Duration::from_secs(1))
+            ]
+        );
+        assert_eq!(entries[2].options.cwd.as_deref(), Some("sub"));
+    }
+
+    #[test]
+    fn env_options_are_merged() {
+        let parsed = parse_line("/tmp/repo env.A=1 env.B=2 env.A=3").unwrap();
+        assert_eq!(
+            parsed.options.env,
+            vec![
+                ("B".to_string(), "2".to_string()),
+                ("A".to_string(), "3".to_string())
+            ]
+        );
+
+        let defaults = EntryOptions {
+            env: vec![
+                ("A".to_string(), "1".to_string()),
+                ("B".to_string(), "1".to_string()),
+            ],
+            ..Default::default()
+        };
+        let line = EntryOptions {
+            env: vec![
+                ("B".to_string(), "2".to_string()),
+                ("C".to_string(), "2".to_string()),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            defaults.merged(&line).env,
+            vec![
+                ("A".to_string(), "1".to_string()),
+                ("B".to_string(), "2".to_string()),
+                ("C".to_string(), "2".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn after_needs_a_path() {
+        let parsed = parse_line("after=a,b").unwrap();
+        assert_eq!(parsed.path, "");
+        assert_eq!(parsed.after, vec!["a", "b"]);
+
+        let error = with_config("after", "/tmp/a\nafter=/tmp/a\n", |path| {
+            entries(path).unwrap().find_map(Result::err)
+        })
+        .unwrap();
+        assert!(error.ends_with(":2: after= needs a path"), "{}", error);
+    }
+
+    #[test]
+    fn lines_that_are_not_utf8_are_reported() {
+        let entries: Vec<_> = with_config("utf8", b"/tmp/a\n/tmp/\xff\n/tmp/b\r\n", |path| {
+            entries(path).unwrap().collect()
+        });
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].as_ref().unwrap().path, "/tmp/a");
+        let error = entries[1].as_ref().err().unwrap();
+        assert!(error.ends_with(":2: not valid UTF-8"), "{}", error);
+        assert_eq!(entries[2].as_ref().unwrap().path, "/tmp/b");
+    }
+
+    #[test]
+    fn invalid_timeout() {
+        assert!(parse_line("/tmp/repo timeout=soon").is_err());
+        assert!(parse_line("/tmp/repo timeout=100000000000000000000000s").is_err());
+
+        let error = with_config("timeout", "/tmp/a timeout=soon\n", |path| {
+            entries(path).unwrap().find_map(Result::err)
+        })
+        .unwrap();
+        assert!(error.contains(":1: "), "{}", error);
+
+        // left out, not fatal
+        let entries = with_config("skipped", "/tmp/a timeout=soon\n/tmp/b\n", get_entries).unwrap();
+        let paths: Vec<&str> = entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, vec!["/tmp/b"]);
+    }
+}
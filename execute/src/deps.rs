@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+use shellexpand::full;
+use smol::channel::{Receiver, Sender};
+
+use crate::config::{Entry, EntryOptions};
+use crate::interrupt;
+use crate::pool::{Event, Job, Pool, Progress};
+use crate::task::TaskResult;
+use crate::throttle::remote_host;
+
+// Every repo may list what it has to run after, one path or name per line.
+// Relative paths start at the repo.
+pub const DEFAULT_DEPS_FILE: &str = ".execute-deps";
+
+// paths compare after resolving symlinks and `..`, if they exist
+fn key(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// The config's paths and, per path, the indices of the paths it runs after.
+pub struct Graph {
+    paths: Vec<String>,
+    options: Vec<Arc<EntryOptions>>,
+    after: Vec<Vec<usize>>,
+}
+
+struct Lookup {
+    by_path: HashMap<PathBuf, usize>,
+    by_name: HashMap<String, Vec<usize>>,
+}
+
+impl Lookup {
+    fn new(paths: &[String]) -> Lookup {
+        let mut lookup = Lookup {
+            by_path: HashMap::new(),
+            by_name: HashMap::new(),
+        };
+        for (i, path) in paths.iter().enumerate() {
+            lookup.by_path.entry(key(Path::new(path))).or_insert(i);
+            if let Some(name) = Path::new(path).file_name() {
+                let name = name.to_string_lossy().to_string();
+                lookup.by_name.entry(name).or_default().push(i);
+            }
+        }
+        lookup
+    }
+
+    // `lib-a` is the one path named like that, `../lib-a` starts at `base`
+    fn resolve(&self, dependency: &str, base: &Path) -> Result<usize, String> {
+        if dependency.contains('/') {
+            return match self.by_path.get(&key(&base.join(dependency))) {
+                Some(i) => Ok(*i),
+                None => Err(format!("{} is not in the config", dependency)),
+            };
+        }
+        match self.by_name.get(dependency).map(Vec::as_slice) {
+            Some([i]) => Ok(*i),
+            Some(_) => Err(format!(
+                "{} names several paths of the config, use its path",
+                dependency
+            )),
+            None => Err(format!("{} is not in the config", dependency)),
+        }
+    }
+}
+
+fn read_deps_file(file: &Path) -> io::Result<Vec<String>> {
+    let content = match fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match full(line) {
+            Ok(expanded) => expanded.into_owned(),
+            Err(_) => line.to_string(),
+        })
+        .collect())
+}
+
+impl Graph {
+    // `after=` of the config plus `deps_file` of every repo, errors on
+    // unknown dependencies and cycles.
+    pub fn build(entries: Vec<Entry>, deps_file: &str) -> Result<Graph, String> {
+        let paths: Vec<String> = entries.iter().map(|entry| entry.path.clone()).collect();
+        let lookup = Lookup::new(&paths);
+        let cwd = std::env::current_dir().unwrap_or_default();
+
+        let mut after: Vec<Vec<usize>> = Vec::with_capacity(paths.len());
+        for entry in &entries {
+            let mut dependencies = Vec::new();
+            for dependency in &entry.after {
+                let i = lookup
+                    .resolve(dependency, &cwd)
+                    .map_err(|e| format!("{}: after={}", entry.path, e))?;
+                dependencies.push(i);
+            }
+
+            let file = Path::new(&entry.path).join(deps_file);
+            let listed = read_deps_file(&file)
+                .map_err(|e| format!("cannot read {}: {}", file.display(), e))?;
+            for dependency in &listed {
+                let i = lookup
+                    .resolve(dependency, Path::new(&entry.path))
+                    .map_err(|e| format!("{}: {}", file.display(), e))?;
+                dependencies.push(i);
+            }
+
+            dependencies.sort_unstable();
+            dependencies.dedup();
+            after.push(dependencies);
+        }
+
+        let options = entries.into_iter().map(|entry| entry.options).collect();
+        let graph = Graph {
+            paths,
+            options,
+            after,
+        };
+        if let Some(cycle) = graph.find_cycle() {
+            let cycle: Vec<&str> = cycle.iter().map(|i| graph.paths[*i].as_str()).collect();
+            return Err(format!("dependency cycle: {}", cycle.join(" -> ")));
+        }
+        Ok(graph)
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    // a path, one it runs after, ..., the same path again
+    fn find_cycle(&self) -> Option<Vec<usize>> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Mark {
+            New,
+            OnStack,
+            Done,
+        }
+        let mut marks = vec![Mark::New; self.paths.len()];
+        // iterative, a long chain of repos must not overflow the stack
+        for root in 0..self.paths.len() {
+            if marks[root] != Mark::New {
+                continue;
+            }
+            let mut stack: Vec<(usize, usize)> = vec![(root, 0)];
+            marks[root] = Mark::OnStack;
+            while let Some((node, next)) = stack.last_mut() {
+                let node = *node;
+                let Some(&dependency) = self.after[node].get(*next) else {
+                    marks[node] = Mark::Done;
+                    stack.pop();
+                    continue;
+                };
+                *next += 1;
+                match marks[dependency] {
+                    Mark::New => {
+                        marks[dependency] = Mark::OnStack;
+                        stack.push((dependency, 0));
+                    }
+                    Mark::OnStack => {
+                        let start = stack
+                            .iter()
+                            .position(|(on_stack, _)| *on_stack == dependency)
+                            .unwrap_or(0);
+                        let mut cycle: Vec<usize> = stack[start..]
+                            .iter()
+                            .map(|(on_stack, _)| *on_stack)
+                            .collect();
+                        cycle.push(dependency);
+                        return Some(cycle);
+                    }
+                    Mark::Done => {}
+                }
+            }
+        }
+        None
+    }
+}
+
+fn skipped(pool: &Pool, id: usize, path: &str, dependency: &str) -> TaskResult {
+    let mut argv = vec![pool.cmd.clone()];
+    argv.extend(pool.cmd_args.iter().cloned());
+    let mut result = TaskResult::skipped(
+        vec![path.to_string()],
+        Vec::new(),
+        argv,
+        PathBuf::from(path),
+        format!("{} did not succeed", dependency),
+    );
+    result.job = id;
+    result
+}
+
+struct Scheduler {
+    graph: Graph,
+    pool: Arc<Pool>,
+    // every path's job until it is queued
+    pending: Vec<Option<Job>>,
+    // closing it ends the workers
+    jobs: Sender<Job>,
+    results: Sender<Option<TaskResult>>,
+    // dependencies every path still waits for
+    waiting: Vec<usize>,
+    dependents: Vec<Vec<usize>>,
+    // queued, finished or skipped
+    handled: Vec<bool>,
+    in_flight: usize,
+}
+
+impl Scheduler {
+    async fn queue(&mut self, i: usize) {
+        self.handled[i] = true;
+        if let Some(mut job) = self.pending[i].take() {
+            // waiting for its dependencies is not waiting for a worker
+            job.queued = Instant::now();
+            self.in_flight += 1;
+            let _ = self.jobs.send(job).await;
+        }
+    }
+
+    // everything that (transitively) runs after `failed`
+    async fn skip_dependents(&mut self, failed: usize) {
+        let mut pending = self.dependents[failed].clone();
+        while let Some(i) = pending.pop() {
+            if self.handled[i] {
+                continue;
+            }
+            self.handled[i] = true;
+            let result = skipped(
+                &self.pool,
+                i,
+                &self.graph.paths[i],
+                &self.graph.paths[failed],
+            );
+            let _ = self.results.send(Some(result)).await;
+            pending.extend(self.dependents[i].iter().copied());
+        }
+    }
+
+    async fn finished(&mut self, i: usize, success: bool) {
+        if !success {
+            self.skip_dependents(i).await;
+            return;
+        }
+        for dependent in self.dependents[i].clone() {
+            self.waiting[dependent] -= 1;
+            if self.waiting[dependent] == 0 && !self.handled[dependent] {
+                self.queue(dependent).await;
+            }
+        }
+    }
+
+    // after an interrupt nothing new starts, what is left counts as not started
+    async fn give_up(&mut self) {
+        for i in 0..self.graph.len() {
+            if !self.handled[i] {
+                self.handled[i] = true;
+                let _ = self.results.send(None).await;
+            }
+        }
+    }
+
+    async fn run(mut self, pool_results: Receiver<Option<TaskResult>>) {
+        for i in 0..self.graph.len() {
+            if self.waiting[i] == 0 {
+                self.queue(i).await;
+            }
+        }
+        while self.in_flight > 0 {
+            let Ok(result) = pool_results.recv().await else {
+                break;
+            };
+            self.in_flight -= 1;
+            let finished = result
+                .as_ref()
+                .map(|result| (result.job, result.outcome.is_success()));
+            // a failure is reported before what it causes to be skipped
+            let _ = self.results.send(result).await;
+            if interrupt::stop_requested() {
+                self.give_up().await;
+            } else if let Some((i, success)) = finished {
+                self.finished(i, success).await;
+            }
+        }
+    }
+}
+
+// Runs the paths of `graph` on the pool, every path once all it runs after
+// succeeded, in parallel where nothing stands in the way. Paths after a
+// failure are not run but reported as skipped. Results like `Pool::start`.
+pub fn start(
+    graph: Graph,
+    pool: &Arc<Pool>,
+    lookup_hosts: bool,
+    progress: Arc<Progress>,
+    events: Option<Sender<Event>>,
+) -> Receiver<Option<TaskResult>> {
+    let count = graph.len();
+    // the graph is complete before the first task starts
+    progress.paths.store(count, Ordering::SeqCst);
+    progress.jobs.store(count, Ordering::SeqCst);
+    progress.complete.store(true, Ordering::SeqCst);
+
+    let (results, receiver) = smol::channel::unbounded();
+    let pool = pool.clone();
+    smol::spawn(async move {
+        let mut hosts = vec![None; count];
+        if lookup_hosts {
+            let paths = graph.paths.clone();
+            hosts =
+                smol::unblock(move || paths.iter().map(|path| remote_host(path)).collect()).await;
+        }
+        let mut dependents = vec![Vec::new(); count];
+        for (i, after) in graph.after.iter().enumerate() {
+            for dependency in after {
+                dependents[*dependency].push(i);
+            }
+        }
+        let mut pending = Vec::with_capacity(count);
+        for (i, host) in hosts.into_iter().enumerate() {
+            let job = Job {
+                id: i,
+                paths: vec![graph.paths[i].clone()],
+                params: Vec::new(),
+                options: graph.options[i].clone(),
+                host,
+                queued: Instant::now(),
+            };
+            // all of them show up as queued, the order is ours to decide
+            if let Some(events) = events.as_ref() {
+                let _ = events.send(Event::Queued(job.clone())).await;
+            }
+            pending.push(Some(job));
+        }
+        let (jobs, jobs_receiver) = smol::channel::unbounded();
+        let pool_results = pool.start(jobs_receiver);
+        let scheduler = Scheduler {
+            waiting: graph.after.iter().map(Vec::len).collect(),
+            graph,
+            pool,
+            pending,
+            jobs,
+            results,
+            dependents,
+            handled: vec![false; count],
+            in_flight: 0,
+        };
+        scheduler.run(pool_results).await;
+    })
+    .detach();
+    receiver
+}
@@ -0,0 +1,80 @@
+use std::cmp::Reverse;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{Map, Value, json};
+
+use crate::config::Entry;
+use crate::store::{read_json, write_json};
+
+// keeps the store small even with `--files` over big trees
+const MAX_ENTRIES: usize = 20_000;
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn key(path: &str, command: &str) -> String {
+    format!("{}\t{}", path, command)
+}
+
+// Durations of earlier runs per (path, command), kept in `durations.json`:
+//   { "<path>\t<command>": { "ms": <smoothed duration>, "seen": <unix time> } }
+pub struct DurationStore {
+    file: PathBuf,
+    entries: Map<String, Value>,
+}
+
+impl DurationStore {
+    pub fn load(data_dir: PathBuf) -> DurationStore {
+        let file = data_dir.join("durations.json");
+        let mut entries = Map::new();
+        if let Some(Value::Object(map)) = read_json(&file) {
+            entries = map;
+        }
+        DurationStore { file, entries }
+    }
+
+    pub fn expected(&self, path: &str, command: &str) -> Option<Duration> {
+        let ms = self.entries.get(&key(path, command))?.get("ms")?.as_u64()?;
+        Some(Duration::from_millis(ms))
+    }
+
+    pub fn record(&mut self, path: &str, command: &str, duration: Duration) {
+        let mut ms = duration.as_millis() as u64;
+        // smooth out single outliers
+        if let Some(previous) = self.expected(path, command) {
+            ms = (ms + previous.as_millis() as u64) / 2;
+        }
+        self.entries
+            .insert(key(path, command), json!({"ms": ms, "seen": now_secs()}));
+    }
+
+    pub fn save(&mut self) -> io::Result<()> {
+        if self.entries.len() > MAX_ENTRIES {
+            let mut by_age: Vec<(String, u64)> = self
+                .entries
+                .iter()
+                .map(|(k, v)| (k.clone(), v["seen"].as_u64().unwrap_or_default()))
+                .collect();
+            by_age.sort_by_key(|(_, seen)| Reverse(*seen));
+            for (k, _) in by_age.into_iter().skip(MAX_ENTRIES) {
+                self.entries.remove(&k);
+            }
+        }
+        write_json(&self.file, &Value::Object(self.entries.clone()))
+    }
+
+    // Longest expected duration first (LPT). Paths we have never seen keep
+    // their config order and go first, they might just as well be slow.
+    pub fn sort_longest_first(&self, entries: &mut [Entry], command: &str) {
+        entries.sort_by_key(|entry| match self.expected(&entry.path, command) {
+            None => (false, Reverse(Duration::MAX)),
+            Some(expected) => (true, Reverse(expected)),
+        });
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use async_process::Stdio;
+
+// `--load-env dotenv` reads a repo's `.env`, `envrc-static` its `.envrc`
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Dotenv,
+    EnvrcStatic,
+}
+
+impl Format {
+    fn file_name(self) -> &'static str {
+        match self {
+            Format::Dotenv => ".env",
+            Format::EnvrcStatic => ".envrc",
+        }
+    }
+}
+
+pub fn parse_format(value: &str) -> Result<Format, String> {
+    match value {
+        "dotenv" => Ok(Format::Dotenv),
+        "envrc-static" => Ok(Format::EnvrcStatic),
+        _ => Err(format!(
+            "unknown --load-env format {:?}, use `dotenv` or `envrc-static`",
+            value
+        )),
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct LoadEnv {
+    pub format: Format,
+    // source the file with `sh` instead of reading its assignments
+    pub allow_shell: bool,
+}
+
+fn is_name(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// `$NAME` and `${NAME}` from what the file set so far, then from our environment
+fn expand(value: &str, env: &HashMap<String, String>) -> Result<String, String> {
+    if value.contains("$(") || value.contains('`') {
+        return Err("command substitution needs --allow-shell-env".to_string());
+    }
+    let lookup = |name: &str| {
+        env.get(name)
+            .cloned()
+            .or_else(|| std::env::var(name).ok())
+            .unwrap_or_default()
+    };
+    let mut expanded = String::new();
+    let mut rest = value;
+    while let Some(dollar) = rest.find('$') {
+        expanded.push_str(&rest[..dollar]);
+        rest = &rest[dollar + 1..];
+        if let Some(braced) = rest.strip_prefix('{') {
+            let Some(end) = braced.find('}') else {
+                return Err("unterminated ${".to_string());
+            };
+            if !is_name(&braced[..end]) {
+                return Err(format!(
+                    "cannot expand ${{{}}} without a shell",
+                    &braced[..end]
+                ));
+            }
+            expanded.push_str(&lookup(&braced[..end]));
+            rest = &braced[end + 1..];
+            continue;
+        }
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        if end == 0 {
+            expanded.push('$');
+        } else {
+            expanded.push_str(&lookup(&rest[..end]));
+        }
+        rest = &rest[end..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+// `'literal'`, `"expanded \"quoted\""` or `expanded # comment`
+fn parse_value(value: &str, env: &HashMap<String, String>) -> Result<String, String> {
+    let (quoted, rest) = if let Some(single) = value.strip_prefix('\'') {
+        let Some(end) = single.find('\'') else {
+            return Err("unterminated '".to_string());
+        };
+        (single[..end].to_string(), &single[end + 1..])
+    } else if let Some(double) = value.strip_prefix('"') {
+        let mut unescaped = String::new();
+        let mut chars = double.char_indices();
+        let mut end = None;
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    end = Some(i);
+                    break;
+                }
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => unescaped.push('\n'),
+                    // a NUL cannot be in the environment, it stands in for `\$`
+                    Some((_, '$')) => unescaped.push('\0'),
+                    Some((_, c)) => unescaped.push(c),
+                    None => unescaped.push('\\'),
+                },
+                c => unescaped.push(c),
+            }
+        }
+        let Some(end) = end else {
+            return Err("unterminated \"".to_string());
+        };
+        (
+            expand(&unescaped, env)?.replace('\0', "$"),
+            &double[end + 1..],
+        )
+    } else {
+        let value = match value.find(" #") {
+            Some(comment) => &value[..comment],
+            None => value,
+        };
+        return expand(value.trim_end(), env);
+    };
+    let rest = rest.trim_start();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return Err(format!("unexpected {:?} after the value", rest));
+    }
+    Ok(quoted)
+}
+
+// Plain assignments, `export` or not, anything else would need a shell.
+fn parse(content: &str) -> Result<Vec<(String, String)>, String> {
+    let mut env: HashMap<String, String> = HashMap::new();
+    let mut order: Vec<String> = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let parsed = match line.split_once('=') {
+            Some((name, value)) if is_name(name) => {
+                parse_value(value, &env).map(|value| (name.to_string(), value))
+            }
+            _ => Err(format!("{:?} needs --allow-shell-env", line)),
+        };
+        let (name, value) = parsed.map_err(|e| format!("line {}: {}", number + 1, e))?;
+        if !env.contains_key(&name) {
+            order.push(name.clone());
+        }
+        env.insert(name, value);
+    }
+    Ok(order
+        .into_iter()
+        .map(|name| {
+            let value = env.remove(&name).unwrap_or_default();
+            (name, value)
+        })
+        .collect())
+}
+
+// what the shell sets on its own
+const SHELL_VARIABLES: [&str; 4] = ["_", "PWD", "OLDPWD", "SHLVL"];
+
+// Sources the file in `dir` and keeps what it changed in the environment.
+async fn source(dir: &Path, file_name: &str) -> Result<Vec<(String, String)>, String> {
+    let output = async_process::Command::new("sh")
+        .arg("-c")
+        .arg(r#"set -a; . "./$1" >&2; env -0"#)
+        .arg("sh")
+        .arg(file_name)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| format!("cannot run sh: {}", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("sourcing failed: {}", stderr.trim_end()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split('\0')
+        .filter_map(|variable| variable.split_once('='))
+        .filter(|(name, value)| {
+            !SHELL_VARIABLES.contains(name) && std::env::var(name).ok().as_deref() != Some(*value)
+        })
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect())
+}
+
+// The variables a repo's env file sets, none without the file.
+pub async fn load(dir: &Path, load: LoadEnv) -> Result<Vec<(String, String)>, String> {
+    let file_name = load.format.file_name();
+    if load.allow_shell {
+        if !dir.join(file_name).is_file() {
+            return Ok(Vec::new());
+        }
+        return source(dir, file_name)
+            .await
+            .map_err(|e| format!("{}: {}", file_name, e));
+    }
+    match fs::read_to_string(dir.join(file_name)) {
+        Ok(content) => parse(&content).map_err(|e| format!("{}: {}", file_name, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!("cannot read {}: {}", file_name, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(content: &str) -> Vec<(String, String)> {
+        parse(content).unwrap()
+    }
+
+    fn pair(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn command_substitution_is_rejected() {
+        for content in [
+            "A=$(whoami)",
+            "A=\"$(whoami)\"",
+            "A=`whoami`",
+            "A=\"x `whoami`\"",
+        ] {
+            let error = parse(content).unwrap_err();
+            assert!(
+                error.contains("--allow-shell-env"),
+                "{}: {}",
+                content,
+                error
+            );
+        }
+        // nothing is run inside single quotes
+        assert_eq!(parsed("A='$(whoami)'"), vec![pair("A", "$(whoami)")]);
+    }
+
+    #[test]
+    fn escaped_dollar() {
+        assert_eq!(
+            parsed("EXECUTE_TEST_A=1\nB=\"\\$EXECUTE_TEST_A is $EXECUTE_TEST_A\""),
+            vec![
+                pair("EXECUTE_TEST_A", "1"),
+                pair("B", "$EXECUTE_TEST_A is 1")
+            ]
+        );
+    }
+
+    #[test]
+    fn single_and_double_quotes() {
+        assert_eq!(
+            parsed("A=1\nB='$A \"x\" # y'\nC=\"$A \\\"x\\\" # y\"\nD=\"a\\nb\""),
+            vec![
+                pair("A", "1"),
+                pair("B", "$A \"x\" # y"),
+                pair("C", "1 \"x\" # y"),
+                pair("D", "a\nb")
+            ]
+        );
+        assert!(parse("A='open").is_err());
+        assert!(parse("A=\"open").is_err());
+        assert!(parse("A='x' y").is_err());
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            parsed("# a comment\n\nA=value # comment\nB=value#not-a-comment\nC='x' # comment"),
+            vec![
+                pair("A", "value"),
+                pair("B", "value#not-a-comment"),
+                pair("C", "x")
+            ]
+        );
+    }
+
+    #[test]
+    fn export_prefix() {
+        assert_eq!(
+            parsed("export A=1\nexport   B=2\nexported=3"),
+            vec![pair("A", "1"), pair("B", "2"), pair("exported", "3")]
+        );
+        assert!(parse("export A").is_err());
+    }
+
+    #[test]
+    fn expands_earlier_lines() {
+        assert_eq!(
+            parsed("EXECUTE_TEST_B=a\nC=${EXECUTE_TEST_B}b\nD=\"$C/c\"\nEXECUTE_TEST_B=z"),
+            vec![
+                pair("EXECUTE_TEST_B", "z"),
+                pair("C", "ab"),
+                pair("D", "ab/c")
+            ]
+        );
+        assert!(parse("A=${B:-default}").is_err());
+        assert!(parse("A=${B").is_err());
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = parse("A=1\nif true; then\n").unwrap_err();
+        assert!(error.starts_with("line 2: "), "{}", error);
+    }
+}
@@ -0,0 +1,465 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use serde_json::{Value, json};
+
+use crate::matrix::{self, Params};
+use crate::store::{read_json, write_json};
+use crate::task::TaskResult;
+
+// a single chatty task must not eat the whole store
+const MAX_OUTPUT_BYTES: usize = 1 << 20;
+
+pub const DEFAULT_KEEP_RUNS: usize = 100;
+pub const DEFAULT_MAX_BYTES: u64 = 256 << 20;
+
+// One directory per run under `<data dir>/history`, named so that they sort
+// by start time:
+//   <id>/run.json     argv, command, config, start, end and counts
+//   <id>/tasks.jsonl  one line per task result, including its output
+pub fn history_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("history")
+}
+
+fn truncated(output: &str) -> (&str, bool) {
+    if output.len() <= MAX_OUTPUT_BYTES {
+        return (output, false);
+    }
+    let mut end = MAX_OUTPUT_BYTES;
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    (&output[..end], true)
+}
+
+pub struct HistoryWriter {
+    dir: PathBuf,
+    run: Value,
+    tasks: BufWriter<File>,
+    ok: usize,
+    failed: usize,
+}
+
+impl HistoryWriter {
+    pub fn create(
+        data_dir: &Path,
+        argv: &[String],
+        command: &[String],
+        config: &str,
+    ) -> io::Result<HistoryWriter> {
+        let started = Local::now();
+        let id = format!("{}-{}", started.format("%Y%m%d-%H%M%S"), std::process::id());
+        let dir = history_dir(data_dir).join(&id);
+        fs::create_dir_all(&dir)?;
+
+        let run = json!({
+            "id": id,
+            "argv": argv,
+            "command": command,
+            "config": config,
+            "cwd": std::env::current_dir().unwrap_or_default().to_string_lossy(),
+            "started": started.to_rfc3339(),
+            "finished": null,
+            "interrupted_by": null,
+            "ok": 0,
+            "failed": 0,
+        });
+        // written right away, so runs that crash still show up
+        write_json(&dir.join("run.json"), &run)?;
+        let tasks = BufWriter::new(File::create(dir.join("tasks.jsonl"))?);
+        Ok(HistoryWriter {
+            dir,
+            run,
+            tasks,
+            ok: 0,
+            failed: 0,
+        })
+    }
+
+    pub fn record(&mut self, result: &TaskResult) -> io::Result<()> {
+        if result.outcome.is_success() {
+            self.ok += 1;
+        } else {
+            self.failed += 1;
+        }
+        let (stdout, stdout_truncated) = truncated(&result.stdout);
+        let (stderr, stderr_truncated) = truncated(&result.stderr);
+        let mut task = result.to_json();
+        task["stdout"] = json!(stdout);
+        task["stderr"] = json!(stderr);
+        task["truncated"] = json!(stdout_truncated || stderr_truncated);
+        serde_json::to_writer(&mut self.tasks, &task).map_err(io::Error::other)?;
+        self.tasks.write_all(b"\n")
+    }
+
+    pub fn finish(mut self, interrupted_by: Option<i32>) -> io::Result<()> {
+        self.tasks.flush()?;
+        self.run["finished"] = json!(Local::now().to_rfc3339());
+        self.run["interrupted_by"] = json!(interrupted_by);
+        self.run["ok"] = json!(self.ok);
+        self.run["failed"] = json!(self.failed);
+        write_json(&self.dir.join("run.json"), &self.run)
+    }
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .filter_map(|entry| entry.ok()?.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+// Drops the oldest runs until at most `keep_runs` are left and they take
+// no more than `max_bytes`. The newest run is always kept.
+pub fn prune(data_dir: &Path, keep_runs: usize, max_bytes: u64) -> io::Result<usize> {
+    let mut run_dirs = run_dirs(&history_dir(data_dir))?;
+    // newest first
+    run_dirs.reverse();
+
+    let mut removed = 0;
+    let mut total: u64 = 0;
+    for (i, dir) in run_dirs.iter().enumerate() {
+        total += dir_size(dir);
+        if i > 0 && (i >= keep_runs || total > max_bytes) {
+            fs::remove_dir_all(dir)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+// oldest first
+fn run_dirs(history_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut dirs: Vec<PathBuf> = match fs::read_dir(history_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.join("run.json").is_file())
+            .collect(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    dirs.sort();
+    Ok(dirs)
+}
+
+struct Run {
+    dir: PathBuf,
+    meta: Value,
+}
+
+impl Run {
+    fn id(&self) -> &str {
+        self.meta["id"].as_str().unwrap_or_default()
+    }
+
+    fn command(&self) -> String {
+        let words: Vec<&str> = self.meta["command"]
+            .as_array()
+            .map(|words| words.iter().filter_map(|word| word.as_str()).collect())
+            .unwrap_or_default();
+        words.join(" ")
+    }
+
+    fn started(&self) -> Option<DateTime<Local>> {
+        let started = self.meta["started"].as_str()?;
+        Some(
+            DateTime::parse_from_rfc3339(started)
+                .ok()?
+                .with_timezone(&Local),
+        )
+    }
+
+    fn took(&self) -> String {
+        let finished = self.meta["finished"]
+            .as_str()
+            .and_then(|finished| DateTime::parse_from_rfc3339(finished).ok());
+        match (self.started(), finished) {
+            (Some(started), Some(finished)) => {
+                let ms = (finished.with_timezone(&Local) - started).num_milliseconds();
+                format!("{:.1}s", ms.max(0) as f64 / 1000.0)
+            }
+            _ => "-".to_string(),
+        }
+    }
+
+    fn tasks(&self) -> io::Result<Vec<Value>> {
+        let file = File::open(self.dir.join("tasks.jsonl"))?;
+        Ok(BufReader::new(file)
+            .lines()
+            .map_while(|line| line.ok())
+            // a run that crashed may end in half a line
+            .filter_map(|line| serde_json::from_str(&line).ok())
+            .collect())
+    }
+}
+
+fn load_runs(data_dir: &Path) -> io::Result<Vec<Run>> {
+    Ok(run_dirs(&history_dir(data_dir))?
+        .into_iter()
+        .filter_map(|dir| {
+            let meta = read_json(&dir.join("run.json"))?;
+            Some(Run { dir, meta })
+        })
+        .collect())
+}
+
+// `3` is the third newest run (as numbered by `list`), anything else is
+// (the start of) a run id
+fn find_run<'a>(runs: &'a [Run], spec: &str) -> Result<&'a Run, String> {
+    if let Ok(n) = spec.parse::<usize>()
+        && n >= 1
+        && n <= runs.len()
+        && spec.len() < 8
+    {
+        return Ok(&runs[runs.len() - n]);
+    }
+    let matching: Vec<&Run> = runs
+        .iter()
+        .filter(|run| run.id().starts_with(spec))
+        .collect();
+    match matching.as_slice() {
+        [run] => Ok(run),
+        [] => Err(format!("no run {:?}, see `execute history list`", spec)),
+        _ => Err(format!("{:?} matches {} runs", spec, matching.len())),
+    }
+}
+
+fn task_paths(task: &Value) -> Vec<String> {
+    task["paths"]
+        .as_array()
+        .map(|paths| {
+            paths
+                .iter()
+                .filter_map(|path| path.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// ` [PYTHON=3.12]` for --matrix tasks
+fn task_params(task: &Value) -> String {
+    let Some(params) = task["params"].as_object() else {
+        return String::new();
+    };
+    let params: Params = params
+        .iter()
+        .map(|(name, value)| (name.clone(), value.as_str().unwrap_or_default().to_string()))
+        .collect();
+    matrix::label(&params)
+}
+
+fn task_status(task: &Value) -> String {
+    let kind = task["outcome"].as_str().unwrap_or("?");
+    if let Some(exit) = task["exit"].as_i64()
+        && exit != 0
+    {
+        return format!("{} {}", kind, exit);
+    }
+    if let Some(signal) = task["signal"].as_i64() {
+        return format!("{} {}", kind, signal);
+    }
+    kind.to_string()
+}
+
+fn print_output(task: &Value) {
+    let stdout = task["stdout"].as_str().unwrap_or_default();
+    let stderr = task["stderr"].as_str().unwrap_or_default();
+    if !stdout.is_empty() {
+        println!("{}", stdout);
+    }
+    if !stderr.is_empty() {
+        println!("[.] stderr:\n{}", stderr);
+    }
+    if task["truncated"].as_bool() == Some(true) {
+        println!("[.] output truncated");
+    }
+}
+
+fn list(runs: &[Run], limit: usize) {
+    for (n, run) in runs.iter().rev().enumerate().take(limit) {
+        let started = run
+            .started()
+            .map(|started| started.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        let mut counts = format!(
+            "{} ok, {} failed",
+            run.meta["ok"].as_u64().unwrap_or(0),
+            run.meta["failed"].as_u64().unwrap_or(0)
+        );
+        if run.meta["finished"].is_null() {
+            counts.push_str(", unfinished");
+        } else if !run.meta["interrupted_by"].is_null() {
+            counts.push_str(", interrupted");
+        }
+        println!(
+            "{:>3}  {}  {}  {:>7}  {}  {} ({})",
+            n + 1,
+            run.id(),
+            started,
+            run.took(),
+            counts,
+            run.command(),
+            run.meta["config"].as_str().unwrap_or_default()
+        );
+    }
+}
+
+fn show(run: &Run, with_output: bool) -> io::Result<()> {
+    println!("run:     {}", run.id());
+    println!("command: {}", run.command());
+    println!(
+        "config:  {}",
+        run.meta["config"].as_str().unwrap_or_default()
+    );
+    println!("started: {}", run.meta["started"].as_str().unwrap_or("-"));
+    println!("took:    {}", run.took());
+    for task in run.tasks()? {
+        println!(
+            "{}[{}] {}ms {}{}",
+            if with_output { "--\n" } else { "" },
+            task_status(&task),
+            task["duration_ms"].as_u64().unwrap_or(0),
+            task_paths(&task).join(" "),
+            task_params(&task)
+        );
+        if with_output {
+            print_output(&task);
+        }
+    }
+    Ok(())
+}
+
+// newest first, what a path produced across runs
+fn path_output(runs: &[Run], path: &str, limit: usize) -> io::Result<()> {
+    let mut shown = 0;
+    'runs: for run in runs.iter().rev() {
+        for task in run.tasks()? {
+            if !task_paths(&task).iter().any(|task_path| task_path == path) {
+                continue;
+            }
+            // every --matrix combination of the path is an entry of its own
+            if shown >= limit {
+                break 'runs;
+            }
+            println!(
+                "--\n{}  {}  {}{}  [{}] {}ms",
+                run.id(),
+                task["started"].as_str().unwrap_or("-"),
+                run.command(),
+                task_params(&task),
+                task_status(&task),
+                task["duration_ms"].as_u64().unwrap_or(0)
+            );
+            print_output(&task);
+            shown += 1;
+        }
+    }
+    if shown == 0 {
+        println!("{} is in none of the recorded runs", path);
+    }
+    Ok(())
+}
+
+fn outcomes(run: &Run) -> io::Result<BTreeMap<String, String>> {
+    let mut outcomes = BTreeMap::new();
+    for task in run.tasks()? {
+        let status = task_status(&task);
+        let params = task_params(&task);
+        for path in task_paths(&task) {
+            outcomes.insert(format!("{}{}", path, params), status.clone());
+        }
+    }
+    Ok(outcomes)
+}
+
+fn diff(old: &Run, new: &Run) -> io::Result<()> {
+    let old_outcomes = outcomes(old)?;
+    let new_outcomes = outcomes(new)?;
+    println!("--- {}  {}", old.id(), old.command());
+    println!("+++ {}  {}", new.id(), new.command());
+
+    let (mut changed, mut removed, mut added) = (0, 0, 0);
+    for (path, old_status) in &old_outcomes {
+        match new_outcomes.get(path) {
+            Some(new_status) if new_status != old_status => {
+                println!("~ {}: {} -> {}", path, old_status, new_status);
+                changed += 1;
+            }
+            Some(_) => {}
+            None => {
+                println!("- {}: {}", path, old_status);
+                removed += 1;
+            }
+        }
+    }
+    for (path, new_status) in &new_outcomes {
+        if !old_outcomes.contains_key(path) {
+            println!("+ {}: {}", path, new_status);
+            added += 1;
+        }
+    }
+    println!(
+        "{} changed, {} only in the old run, {} only in the new run",
+        changed, removed, added
+    );
+    Ok(())
+}
+
+fn usage() -> String {
+    r#"usage: execute history <command>
+  commands:
+    list [--limit <num>] ... recorded runs, newest first [default: 20]
+    show <run> [--output] ... outcome and duration of every task of a run, with their output
+    path <path> [--limit <num>] ... what a path produced across runs, newest first [default: 5]
+    diff [<old run> <new run>] ... tasks whose outcome changed [default: the two newest runs]
+    dir ... where runs are stored
+  <run> is the number `list` shows (1 is the newest) or (the start of) a run id"#
+        .to_string()
+}
+
+// `execute history ...`
+pub fn command(args: Vec<OsString>, data_dir: &Path) -> Result<(), lexopt::Error> {
+    use lexopt::prelude::*;
+
+    let mut parser = lexopt::Parser::from_args(args);
+    let mut values: Vec<String> = Vec::new();
+    let mut limit: Option<usize> = None;
+    let mut with_output = false;
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Long("limit") => limit = Some(parser.value()?.parse()?),
+            Long("output") => with_output = true,
+            Short('h') | Long("help") => return Err(usage().into()),
+            Value(val) => values.push(val.string()?),
+            _ => return Err(arg.unexpected()),
+        }
+    }
+
+    let runs = load_runs(data_dir)
+        .map_err(|e| format!("cannot read history in {:?}: {}", history_dir(data_dir), e))?;
+    let values: Vec<&str> = values.iter().map(String::as_str).collect();
+    let io_result = match values.as_slice() {
+        ["list"] => {
+            list(&runs, limit.unwrap_or(20));
+            Ok(())
+        }
+        ["show", run] => show(find_run(&runs, run)?, with_output),
+        ["path", path] => path_output(&runs, path, limit.unwrap_or(5)),
+        ["diff"] => diff(find_run(&runs, "2")?, find_run(&runs, "1")?),
+        ["diff", old, new] => diff(find_run(&runs, old)?, find_run(&runs, new)?),
+        ["dir"] => {
+            println!("{}", history_dir(data_dir).display());
+            Ok(())
+        }
+        _ => return Err(usage().into()),
+    };
+    io_result.map_err(|e| format!("cannot read history: {}", e).into())
+}
@@ -0,0 +1,166 @@
+use std::io::{self, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+
+use crate::log_err;
+use crate::matrix::Params;
+use crate::task::{Outcome, TaskResult};
+
+// Shell commands around the run and around every task, e.g.
+//
+//   --before-each 'test -f Makefile' --after-all 'notify-send "$(jq .failed)"'
+#[derive(Clone, Default)]
+pub struct Hooks {
+    pub before_all: Option<String>,
+    pub after_all: Option<String>,
+    // in the task's directory, non-zero skips the task
+    pub before_each: Option<String>,
+    // in the task's directory, knows how the task went
+    pub after_each: Option<String>,
+}
+
+// `exited with 1`, `was killed by signal 9`
+fn describe(status: ExitStatus) -> String {
+    match status.code() {
+        Some(code) => format!("exited with {}", code),
+        None => format!(
+            "was killed by signal {}",
+            status.signal().unwrap_or_default()
+        ),
+    }
+}
+
+// before and after the run: our directory, stdout and stderr
+fn run_blocking(name: &str, hook: &str, input: Option<&[u8]>) -> Result<(), String> {
+    let mut command = std::process::Command::new("sh");
+    command.arg("-c").arg(hook);
+    if input.is_some() {
+        command.stdin(Stdio::piped());
+    }
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("cannot run {}: {}", name, e))?;
+    if let Some(input) = input
+        && let Some(mut stdin) = child.stdin.take()
+    {
+        // the hook may not care about its stdin
+        let _ = stdin.write_all(input);
+    }
+    let status = child
+        .wait()
+        .map_err(|e| format!("cannot run {}: {}", name, e))?;
+    if !status.success() {
+        return Err(format!("{} {}", name, describe(status)));
+    }
+    Ok(())
+}
+
+pub fn before_all(hook: &str) -> Result<(), String> {
+    run_blocking("--before-all", hook, None)
+}
+
+// `summary` is handed over on stdin
+pub fn after_all(hook: &str, summary: &serde_json::Value) -> Result<(), String> {
+    let mut input = summary.to_string();
+    input.push('\n');
+    run_blocking("--after-all", hook, Some(input.as_bytes()))
+}
+
+struct HookOutput {
+    status: ExitStatus,
+    stdout: String,
+    stderr: String,
+}
+
+// why a task must not run, with what --before-each printed
+pub struct Veto {
+    pub reason: String,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+// what every per-task hook and --sh script knows about its task
+pub fn task_env(paths: &[String], params: &Params) -> Vec<(String, String)> {
+    let mut env = vec![
+        ("EXECUTE_PATH".to_string(), paths[0].clone()),
+        ("EXECUTE_PATHS".to_string(), paths.join("\n")),
+    ];
+    env.extend(params.iter().cloned());
+    env
+}
+
+async fn run_in(hook: &str, cwd: &Path, env: Vec<(String, String)>) -> io::Result<HookOutput> {
+    let output = async_process::Command::new("sh")
+        .arg("-c")
+        .arg(hook)
+        .current_dir(cwd)
+        .envs(env)
+        .stdin(Stdio::null())
+        .output()
+        .await?;
+    let text = |bytes: &[u8]| {
+        let text = String::from_utf8_lossy(bytes);
+        text.strip_suffix('\n').unwrap_or(&text).to_string()
+    };
+    Ok(HookOutput {
+        status: output.status,
+        stdout: text(&output.stdout),
+        stderr: text(&output.stderr),
+    })
+}
+
+pub async fn before_each(
+    hook: &str,
+    cwd: &Path,
+    paths: &[String],
+    params: &Params,
+) -> Result<(), Veto> {
+    match run_in(hook, cwd, task_env(paths, params)).await {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => Err(Veto {
+            reason: format!("--before-each {}", describe(output.status)),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        }),
+        Err(e) => Err(Veto {
+            reason: format!("cannot run --before-each: {}", e),
+            stdout: String::new(),
+            stderr: String::new(),
+        }),
+    }
+}
+
+// failures are only reported, the task is done already
+pub async fn after_each(hook: &str, result: &TaskResult) {
+    let mut env = task_env(&result.paths, &result.params);
+    let mut exit = String::new();
+    if let Outcome::Exited(code) = result.outcome {
+        exit = code.to_string();
+    }
+    env.push((
+        "EXECUTE_OUTCOME".to_string(),
+        result.outcome.kind().to_string(),
+    ));
+    env.push(("EXECUTE_EXIT".to_string(), exit));
+    env.push((
+        "EXECUTE_DURATION_MS".to_string(),
+        result.duration.as_millis().to_string(),
+    ));
+    match run_in(hook, &result.cwd, env).await {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => {
+            log_err!(
+                "--after-each {} for {}{}",
+                describe(output.status),
+                result.label(),
+                if output.stderr.is_empty() {
+                    String::new()
+                } else {
+                    format!(":\n{}", output.stderr)
+                }
+            );
+        }
+        Err(e) => log_err!("cannot run --after-each for {}: {}", result.label(), e),
+    }
+}
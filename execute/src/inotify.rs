@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr};
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use smol::channel::{Receiver, Sender};
+
+use crate::log_err;
+use crate::task::GREP_EXCLUDES;
+
+// what counts as a change, directories are watched one by one
+const WATCH_MASK: u32 = libc::IN_CLOSE_WRITE
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_DELETE_SELF;
+
+// `*venv*` matches `.venv`, `build.*trace` matches `build.ninja.trace`
+fn wildcard(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| wildcard(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && wildcard(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && wildcard(rest, &name[1..]),
+    }
+}
+
+// The directories and files `grep` skips are noise here as well.
+struct Excludes {
+    dirs: Vec<&'static str>,
+    files: Vec<&'static str>,
+}
+
+impl Excludes {
+    fn from_grep() -> Excludes {
+        let mut excludes = Excludes {
+            dirs: Vec::new(),
+            files: Vec::new(),
+        };
+        for exclude in GREP_EXCLUDES {
+            if let Some(dir) = exclude.strip_prefix("--exclude-dir=") {
+                excludes.dirs.push(dir.trim_matches('"'));
+            } else if let Some(file) = exclude.strip_prefix("--exclude=") {
+                excludes.files.push(file.trim_matches('"'));
+            }
+        }
+        excludes
+    }
+
+    fn dir(&self, name: &OsStr) -> bool {
+        let name = name.as_bytes();
+        self.dirs.iter().any(|dir| wildcard(dir.as_bytes(), name))
+    }
+
+    fn file(&self, name: &OsStr) -> bool {
+        let name = name.as_bytes();
+        self.files
+            .iter()
+            .any(|file| wildcard(file.as_bytes(), name))
+    }
+}
+
+struct Inotify {
+    fd: OwnedFd,
+    // watch descriptor -> (repo, directory)
+    watches: HashMap<i32, (usize, PathBuf)>,
+    excludes: Excludes,
+    warned_about_limit: bool,
+}
+
+impl Inotify {
+    fn new() -> io::Result<Inotify> {
+        // SAFETY: plain syscall, the fd is owned from here on
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Inotify {
+            // SAFETY: `fd` is a fresh descriptor nobody else owns
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            watches: HashMap::new(),
+            excludes: Excludes::from_grep(),
+            warned_about_limit: false,
+        })
+    }
+
+    // watches `dir` and everything below it that is not excluded
+    fn add_tree(&mut self, repo: usize, dir: &Path) {
+        let Ok(c_dir) = CString::new(dir.as_os_str().as_bytes()) else {
+            return;
+        };
+        // SAFETY: `c_dir` is a valid C string for the duration of the call
+        let wd = unsafe {
+            libc::inotify_add_watch(
+                self.fd.as_raw_fd(),
+                c_dir.as_ptr(),
+                WATCH_MASK | libc::IN_ONLYDIR,
+            )
+        };
+        if wd < 0 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() == Some(libc::ENOSPC) && !self.warned_about_limit {
+                log_err!(
+                    "out of inotify watches, raise fs.inotify.max_user_watches to see all changes"
+                );
+                self.warned_about_limit = true;
+            }
+            return;
+        }
+        self.watches.insert(wd, (repo, dir.to_path_buf()));
+
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            // no symlinks, they may point back up
+            if entry.file_type().is_ok_and(|kind| kind.is_dir())
+                && !self.excludes.dir(&entry.file_name())
+            {
+                self.add_tree(repo, &entry.path());
+            }
+        }
+    }
+
+    // Reads events until the receiving side is gone.
+    fn run(mut self, repos: Vec<String>, changes: Sender<String>) {
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            // SAFETY: reads at most `buf.len()` bytes into `buf`
+            let n = unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+            if n < 0 {
+                if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                log_err!("cannot read file changes: {}", io::Error::last_os_error());
+                return;
+            }
+
+            let mut offset = 0;
+            while offset + size_of::<libc::inotify_event>() <= n as usize {
+                // SAFETY: the kernel writes whole events, the header fits (checked above)
+                let event: libc::inotify_event =
+                    unsafe { std::ptr::read_unaligned(buf[offset..].as_ptr().cast()) };
+                let name_start = offset + size_of::<libc::inotify_event>();
+                let name_bytes = &buf[name_start..name_start + event.len as usize];
+                offset = name_start + event.len as usize;
+                // the name is padded with NULs
+                let name_end = name_bytes
+                    .iter()
+                    .position(|&b| b == 0)
+                    .unwrap_or(name_bytes.len());
+                let name = OsStr::from_bytes(&name_bytes[..name_end]);
+
+                if event.mask & libc::IN_IGNORED != 0 {
+                    self.watches.remove(&event.wd);
+                    continue;
+                }
+                let Some((repo, dir)) = self.watches.get(&event.wd).cloned() else {
+                    continue;
+                };
+                let is_dir = event.mask & libc::IN_ISDIR != 0;
+                if (is_dir && self.excludes.dir(name)) || (!is_dir && self.excludes.file(name)) {
+                    continue;
+                }
+                if is_dir && event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+                    self.add_tree(repo, &dir.join(name));
+                }
+                if changes.send_blocking(repos[repo].clone()).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// Watches the repos on a separate thread, every change sends its repo.
+pub fn start(repos: &[String]) -> io::Result<Receiver<String>> {
+    let mut inotify = Inotify::new()?;
+    for (i, repo) in repos.iter().enumerate() {
+        inotify.add_tree(i, Path::new(repo));
+    }
+    let (sender, receiver) = smol::channel::unbounded();
+    let repos = repos.to_vec();
+    std::thread::spawn(move || inotify.run(repos, sender));
+    Ok(receiver)
+}
@@ -0,0 +1,125 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// What kill(2) is called with per running task: `-pgid` for a task in its
+// own process group (pgid == pid of the child), so a signal reaches
+// everything it started, or the pid of a task that shares our process group,
+// whose descendants are looked up when it is signalled.
+static RUNNING: Mutex<BTreeSet<i32>> = Mutex::new(BTreeSet::new());
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+// Whether we are the foreground process group of our terminal. Tasks then
+// stay in our group: a task in a background group that reads the terminal
+// (`read`, ssh and git prompts on /dev/tty) is stopped by SIGTTIN.
+pub fn in_foreground() -> bool {
+    // SAFETY: plain syscalls without memory arguments
+    unsafe {
+        let foreground = libc::tcgetpgrp(libc::STDIN_FILENO);
+        foreground > 0 && foreground == libc::getpgrp()
+    }
+}
+
+pub fn target(pid: i32, own_group: bool) -> i32 {
+    if own_group { -pid } else { pid }
+}
+
+pub fn register(target: i32) {
+    RUNNING.lock().unwrap().insert(target);
+}
+
+pub fn unregister(target: i32) {
+    RUNNING.lock().unwrap().remove(&target);
+}
+
+pub fn request_stop() {
+    STOPPING.store(true, Ordering::SeqCst);
+}
+
+pub fn stop_requested() -> bool {
+    STOPPING.load(Ordering::SeqCst)
+}
+
+// pid -> parent pid of every process we can see
+#[cfg(target_os = "linux")]
+fn parents() -> HashMap<i32, i32> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return HashMap::new();
+    };
+    entries
+        .filter_map(|entry| {
+            let pid: i32 = entry.ok()?.file_name().to_str()?.parse().ok()?;
+            let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+            // the command name in parentheses may contain spaces
+            let (_, fields) = stat.rsplit_once(')')?;
+            let ppid = fields.split_whitespace().nth(1)?.parse().ok()?;
+            Some((pid, ppid))
+        })
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn parents() -> HashMap<i32, i32> {
+    let Ok(output) = std::process::Command::new("ps")
+        .args(["-A", "-o", "pid=", "-o", "ppid="])
+        .output()
+    else {
+        return HashMap::new();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some((fields.next()?.parse().ok()?, fields.next()?.parse().ok()?))
+        })
+        .collect()
+}
+
+// everything `pid` started that is still below it
+fn descendants(pid: i32) -> Vec<i32> {
+    let parents = parents();
+    let mut found = vec![pid];
+    let mut i = 0;
+    while i < found.len() {
+        let parent = found[i];
+        found.extend(
+            parents
+                .iter()
+                .filter(|(_, ppid)| **ppid == parent)
+                .map(|(pid, _)| *pid),
+        );
+        i += 1;
+    }
+    found.remove(0);
+    found
+}
+
+pub fn kill(target: i32, signal: i32) {
+    // a task in our group has no group of its own to signal
+    let descendants = if target > 0 {
+        descendants(target)
+    } else {
+        Vec::new()
+    };
+    // SAFETY: kill(2) has no memory safety requirements
+    unsafe {
+        libc::kill(target, signal);
+        for pid in descendants {
+            libc::kill(pid, signal);
+        }
+    }
+}
+
+// returns how many running tasks there are
+pub fn signal_all(signal: i32) -> usize {
+    let running = RUNNING.lock().unwrap();
+    for target in running.iter() {
+        // the terminal sent Ctrl-C to our whole group already, twice would
+        // look like an impatient second Ctrl-C to some tools
+        if *target > 0 && signal == libc::SIGINT {
+            continue;
+        }
+        kill(*target, signal);
+    }
+    running.len()
+}
@@ -0,0 +1,148 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::matrix;
+use crate::task::{Outcome, TaskResult};
+
+// XML 1.0 does not allow most control characters (e.g. color escapes), drop them
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if (c as u32) < 0x20 => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub struct JunitReport {
+    file: PathBuf,
+    testcases: Vec<String>,
+    failures: usize,
+    errors: usize,
+    skipped: usize,
+    total_time: Duration,
+}
+
+impl JunitReport {
+    pub fn new(file: &Path) -> JunitReport {
+        JunitReport {
+            file: file.to_path_buf(),
+            testcases: Vec::new(),
+            failures: 0,
+            errors: 0,
+            skipped: 0,
+            total_time: Duration::ZERO,
+        }
+    }
+
+    pub fn add(&mut self, result: &TaskResult, classname: &str) {
+        let count = result.paths.len();
+        let mut body = String::new();
+        match &result.outcome {
+            Outcome::Exited(0) => {}
+            Outcome::Exited(ec) => {
+                self.failures += count;
+                body = format!(
+                    "      <failure message=\"Non-zero exit {}\">{}</failure>\n",
+                    ec,
+                    escape(&result.stderr)
+                );
+            }
+            Outcome::Signaled(sig) => {
+                self.failures += count;
+                body = format!(
+                    "      <failure message=\"Killed by signal {}\">{}</failure>\n",
+                    sig,
+                    escape(&result.stderr)
+                );
+            }
+            Outcome::LimitExceeded(sig, reason) => {
+                self.failures += count;
+                body = format!(
+                    "      <failure message=\"Killed by signal {}: {}\">{}</failure>\n",
+                    sig,
+                    reason,
+                    escape(&result.stderr)
+                );
+            }
+            Outcome::TimedOut(to) => {
+                self.failures += count;
+                body = format!(
+                    "      <failure message=\"Timed out after {:?}\">{}</failure>\n",
+                    to,
+                    escape(&result.stderr)
+                );
+            }
+            Outcome::SpawnFailed(err) | Outcome::WaitFailed(err) | Outcome::SandboxFailed(err) => {
+                self.errors += count;
+                body = format!("      <error message=\"{}\"/>\n", escape(err));
+            }
+            Outcome::Skipped(reason) => {
+                self.skipped += count;
+                body = format!("      <skipped message=\"{}\"/>\n", escape(reason));
+            }
+        }
+        if !result.stdout.is_empty() {
+            body.push_str(&format!(
+                "      <system-out>{}</system-out>\n",
+                escape(&result.stdout)
+            ));
+        }
+        if !result.stderr.is_empty() {
+            body.push_str(&format!(
+                "      <system-err>{}</system-err>\n",
+                escape(&result.stderr)
+            ));
+        }
+
+        self.total_time += result.duration;
+        // every path of a batch gets the result of the batch, the --matrix
+        // values tell the runs of a path apart
+        let label = matrix::label(&result.params);
+        for path in &result.paths {
+            self.testcases.push(format!(
+                "    <testcase name=\"{}{}\" classname=\"{}\" time=\"{:.3}\">\n{}    </testcase>\n",
+                escape(path),
+                escape(&label),
+                escape(classname),
+                result.duration.as_secs_f64(),
+                body
+            ));
+        }
+    }
+
+    pub fn write(&self) -> io::Result<()> {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!(
+            "<testsuites tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+            self.testcases.len(),
+            self.failures,
+            self.errors,
+            self.skipped,
+            self.total_time.as_secs_f64()
+        ));
+        xml.push_str(&format!(
+            "  <testsuite name=\"execute\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+            self.testcases.len(),
+            self.failures,
+            self.errors,
+            self.skipped,
+            self.total_time.as_secs_f64()
+        ));
+        for testcase in &self.testcases {
+            xml.push_str(testcase);
+        }
+        xml.push_str("  </testsuite>\n</testsuites>\n");
+        fs::write(&self.file, xml)
+    }
+}
@@ -0,0 +1,213 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// ioprio_set(2), glibc has no wrapper
+#[cfg(target_os = "linux")]
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+#[cfg(target_os = "linux")]
+const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+// what setrlimit(2) takes, glibc has its own type
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type Resource = libc::c_int;
+
+#[derive(Clone, Copy)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub enum IoNice {
+    Realtime(u8),
+    BestEffort(u8),
+    Idle,
+}
+
+#[cfg(target_os = "linux")]
+impl IoNice {
+    fn ioprio(self) -> libc::c_int {
+        let (class, level) = match self {
+            IoNice::Realtime(level) => (1, level),
+            IoNice::BestEffort(level) => (2, level),
+            IoNice::Idle => (3, 0),
+        };
+        (class << IOPRIO_CLASS_SHIFT) | level as libc::c_int
+    }
+}
+
+// `idle`, `best-effort`, `best-effort:7` or `realtime:0`, like ionice(1) levels 0-7
+pub fn parse_ionice(value: &str) -> Result<IoNice, String> {
+    if !cfg!(target_os = "linux") {
+        return Err("--ionice is only supported on Linux".to_string());
+    }
+    let (class, level) = match value.split_once(':') {
+        Some((class, level)) => (class, Some(level)),
+        None => (value, None),
+    };
+    let level = match level {
+        Some(level) => match level.parse::<u8>() {
+            Ok(level) if level <= 7 => level,
+            _ => return Err(format!("invalid ionice level (0-7): {:?}", value)),
+        },
+        // what the kernel assumes without a level
+        None => 4,
+    };
+    match class {
+        "realtime" => Ok(IoNice::Realtime(level)),
+        "best-effort" => Ok(IoNice::BestEffort(level)),
+        "idle" => Ok(IoNice::Idle),
+        _ => Err(format!("unknown ionice class: {:?}", value)),
+    }
+}
+
+// `1048576`, `512K`, `512M` or `4G`
+pub fn parse_bytes(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, factor) = match value.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&value[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&value[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size: {:?}", value))?;
+    number
+        .checked_mul(factor)
+        .ok_or_else(|| format!("size too large: {:?}", value))
+}
+
+// Applied in the child between fork and exec, so every task gets its own.
+#[derive(Clone, Copy, Default)]
+pub struct Limits {
+    pub nice: Option<i32>,
+    pub ionice: Option<IoNice>,
+    // address space in bytes
+    pub address_space: Option<u64>,
+    // cpu time in seconds
+    pub cpu: Option<u64>,
+    pub open_files: Option<u64>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        self.nice.is_none()
+            && self.ionice.is_none()
+            && self.address_space.is_none()
+            && self.cpu.is_none()
+            && self.open_files.is_none()
+    }
+
+    // Runs after fork, only async-signal-safe calls in here.
+    pub fn apply(&self) -> io::Result<()> {
+        if let Some(nice) = self.nice {
+            // SAFETY: plain syscall on our own process
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(ionice) = self.ionice {
+            // SAFETY: plain syscall on our own process
+            let ret = unsafe {
+                libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ionice.ioprio())
+            };
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if let Some(bytes) = self.address_space {
+            set_rlimit(libc::RLIMIT_AS, bytes, bytes)?;
+        }
+        if let Some(seconds) = self.cpu {
+            // the soft limit sends SIGXCPU, a second later the hard one SIGKILL
+            set_rlimit(libc::RLIMIT_CPU, seconds, seconds + 1)?;
+        }
+        if let Some(files) = self.open_files {
+            set_rlimit(libc::RLIMIT_NOFILE, files, files)?;
+        }
+        Ok(())
+    }
+}
+
+fn set_rlimit(resource: Resource, soft: u64, hard: u64) -> io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: soft as libc::rlim_t,
+        rlim_max: hard as libc::rlim_t,
+    };
+    // SAFETY: `limit` outlives the call
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Signals the kernel only sends when a resource limit is hit.
+pub fn exceeded_by_signal(signal: i32) -> Option<&'static str> {
+    match signal {
+        libc::SIGXCPU => Some("CPU time limit exceeded"),
+        libc::SIGXFSZ => Some("file size limit exceeded"),
+        _ => None,
+    }
+}
+
+// How often `CpuUsage` looks, the hard limit is a second past the soft one.
+#[cfg(target_os = "linux")]
+const CPU_SAMPLE_INTERVAL: Duration = Duration::from_millis(200);
+
+// CPU time a task used so far, sampled while it runs. A task that ignores
+// SIGXCPU is killed with SIGKILL at the hard --rlimit-cpu, only the time it
+// used tells that apart from any other SIGKILL.
+pub struct CpuUsage {
+    used_ms: Arc<AtomicU64>,
+    // stops sampling once dropped
+    _sampler: Option<smol::Task<()>>,
+}
+
+impl CpuUsage {
+    #[cfg(target_os = "linux")]
+    pub fn watch(pid: u32) -> CpuUsage {
+        let used_ms = Arc::new(AtomicU64::new(0));
+        let sampled = used_ms.clone();
+        let sampler = smol::spawn(async move {
+            // gone once the task was reaped
+            while let Some(used) = cpu_time(pid) {
+                sampled.store(used.as_millis() as u64, Ordering::Relaxed);
+                smol::Timer::after(CPU_SAMPLE_INTERVAL).await;
+            }
+        });
+        CpuUsage {
+            used_ms,
+            _sampler: Some(sampler),
+        }
+    }
+
+    // nothing to read the CPU time of a running process from
+    #[cfg(not(target_os = "linux"))]
+    pub fn watch(_pid: u32) -> CpuUsage {
+        CpuUsage {
+            used_ms: Arc::new(AtomicU64::new(0)),
+            _sampler: None,
+        }
+    }
+
+    // whether a task that died from `signal` hit the hard CPU time limit
+    pub fn exceeded(&self, signal: i32, limit_seconds: u64) -> bool {
+        let used = Duration::from_millis(self.used_ms.load(Ordering::Relaxed));
+        signal == libc::SIGKILL && used >= Duration::from_secs(limit_seconds)
+    }
+}
+
+// user plus system time from /proc/<pid>/stat
+#[cfg(target_os = "linux")]
+fn cpu_time(pid: u32) -> Option<Duration> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // the command name in parentheses may contain spaces
+    let mut fields = stat.rsplit_once(')')?.1.split_whitespace();
+    // utime and stime are fields 14 and 15, the first after `)` is 3
+    let utime: u64 = fields.nth(11)?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    // SAFETY: plain sysconf query
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+    Some(Duration::from_millis((utime + stime) * 1000 / ticks))
+}
@@ -1,31 +1,96 @@
-use async_process::{Command, Stdio};
-
-// type of BufReader::new(stderr).lines()
-use futures_lite::AsyncBufReadExt;
-use futures_lite::io::BufReader;
-// type of `tasks.next()
+use futures_lite::FutureExt;
+// type of `signals.next()`
 use futures_lite::stream::StreamExt;
-use futures_util::stream::FuturesUnordered;
 
-// type of `task.timeout()` in match
-use smol::lock::Semaphore;
-// to ensure the Semaphore is clonable
-use smol_timeout::TimeoutExt;
+use std::collections::HashMap;
+use std::io::IsTerminal;
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
 
-use std::fs;
 use std::path::PathBuf;
 use std::time::Duration;
 
-use brace_expand::brace_expand;
-use globby::glob;
-use shellexpand::full;
+use async_signal::{Signal, Signals};
 
 mod logging;
 
 mod environment;
 pub use environment::read_env_variables;
 
+mod interrupt;
+
+mod limits;
+use limits::Limits;
+
+mod task;
+use task::{Outcome, RunOptions, TaskResult};
+
+mod pty;
+
+mod stdin;
+use stdin::StdinSource;
+
+mod batch;
+use batch::Batcher;
+
+mod config;
+
+mod deps;
+
+mod aliases;
+
+mod matrix;
+use matrix::Axis;
+
+mod hooks;
+use hooks::Hooks;
+
+mod env_file;
+use env_file::LoadEnv;
+
+mod script;
+use script::Script;
+
+mod pick;
+use pick::Pick;
+
+mod sandbox;
+use sandbox::Sandbox;
+
+mod pool;
+use pool::{Pool, Progress};
+
+mod output_dir;
+use output_dir::OutputDir;
+
+mod junit;
+use junit::JunitReport;
+
+mod timings;
+use timings::Timings;
+
+mod store;
+
+mod durations;
+use durations::DurationStore;
+
+mod history;
+use history::HistoryWriter;
+
+mod watch;
+
+#[cfg(target_os = "linux")]
+mod inotify;
+
+mod throttle;
+use throttle::{HostLimits, LoadGate, StartGate};
+
+mod tui;
+use tui::Tui;
+
+mod progress_line;
+use progress_line::ProgressLine;
+
 struct Args {
     show_header: bool,
     use_color: bool,
@@ -33,7 +98,35 @@ struct Args {
     config_filename: String,
     max_concurrent_tasks: usize,
     timeout: Option<Duration>,
+    output_dir: Option<PathBuf>,
+    junit_file: Option<PathBuf>,
+    timings_top: Option<usize>,
+    schedule_by_history: bool,
+    start_interval: Duration,
+    per_host_limit: Option<usize>,
+    max_load: Option<f64>,
+    pty_size: Option<pty::PtySize>,
+    stdin_broadcast: bool,
+    batch_size: usize,
+    max_args_bytes: Option<usize>,
+    limits: Limits,
+    tui: bool,
+    show_progress: bool,
+    history_keep_runs: Option<usize>,
+    history_max_bytes: u64,
+    watch: Option<Duration>,
+    // the per-repo dependency file, with --deps
+    deps_file: Option<String>,
+    matrix: Vec<Axis>,
+    hooks: Hooks,
+    load_env: Option<LoadEnv>,
+    pick: Option<Pick>,
+    sandbox: Sandbox,
+    // --sh scripts get the paths as arguments
+    is_script: bool,
     command: Vec<String>,
+    // what the run shows as its command, the script with --sh
+    title: Vec<String>,
 }
 
 fn get_usage_info(
@@ -41,23 +134,76 @@ fn get_usage_info(
     config: String,
     timeout: u64,
     report_tasks_step: usize,
+    timings_top: usize,
 ) -> String {
     let rendered = format!(
         r#"usage: execute [options] [flags] -- <args>
+       execute @<alias> [options] [flags] [-- <args>] ... options and flags override the alias'
+       execute history --help ... browse earlier runs
   options:
-    -w/--max-concurrent-tasks <num> [default: {}]
-    -c/--config <file/fd> [default: {}]
+    -w/--max-concurrent-tasks <num|auto|percent> ... `auto` is one per CPU, `150%` scales that, at most 1024 [default: {}]
+    -c/--config <file/fd> ... one path per line, `<path> timeout=30s cwd=<dir> env.NAME=value` overrides for it, a line of only options sets them for the lines below [default: {}]
     -t/--timeout <seconds> [default: {}]
+    --output-dir <dir> ... write stdout, stderr and metadata per task to <dir> (replacing what an earlier run wrote there), print a summary only
+    --junit <file> ... write a JUnit XML report with one testcase per path
+    --timings-top <num> ... number of slowest tasks --timings lists [default: {}]
+    --schedule <config|history> ... start order, `history` starts the longest tasks of earlier runs first [default: config]
+    --start-interval <duration> ... wait at least this long between two task starts, e.g. `200ms`
+    --max-starts-per-second <num> ... start at most <num> tasks per second
+    --per-host-limit <num> ... run at most <num> tasks per git remote host (`origin`) at once
+    --max-load <float> ... start no new tasks while the 1-minute load average or CPU, I/O or memory pressure is too high (Linux only)
+    -n/--batch-size <num> ... pass up to <num> files to one invocation (--files only) [default: 1]
+    --max-args-bytes <num> ... limit the size of a batch's arguments [default: ARG_MAX minus the environment]
+    --pty-size <cols>x<rows> ... size of the --pty terminals [default: size of this terminal or 80x24]
+    --nice <num> ... run every task with this niceness, negative values need privileges
+    --ionice <class[:level]> ... `idle`, `best-effort[:0-7]` or `realtime[:0-7]` I/O scheduling for every task
+    --rlimit-as <bytes> ... address space limit per task, e.g. `4G`
+    --rlimit-cpu <duration> ... CPU time limit per task, SIGXCPU once exceeded
+    --rlimit-nofile <num> ... open files limit per task
+    --matrix <NAME=v1,v2,...> ... run every path once per value, as `{{NAME}}` in <args> and $NAME, repeat for more axes
+    --before-all <sh> ... run before the first task, nothing runs if it fails
+    --after-all <sh> ... run after the last task, with a JSON summary of the run on stdin
+    --before-each <sh> ... run in every task's directory before it, non-zero skips the task
+    --after-each <sh> ... run in every task's directory after it, $EXECUTE_OUTCOME and $EXECUTE_EXIT tell how it went
+    --sh <script> ... run the script instead of a command, the paths are $1... and $EXECUTE_PATH
+    --sh-file <file> ... run the script file instead of a command, like --sh
+    --shell <shell> ... shell for --sh and --sh-file [default: $SHELL or sh]
+    --load-env <dotenv|envrc-static> ... apply every repo's .env or .envrc to its task, plain assignments only, the config's env.NAME wins
+    --deps-file <name> ... file in every repo listing the paths or names it runs after [default: {}]
+    --watch-debounce <duration> ... how long --watch waits for changes to settle [default: 300ms]
+    --history-keep <num> ... number of runs kept in the history [default: {}]
+    --history-max-size <bytes> ... size the history may take, oldest runs go first, e.g. `512M` [default: 256M]
   flags:
+    --no-history ... do not record this run in the history
     --no-color ... disable color for `git` and `grep`  [default: colored]
-    --no-header ... will report remaining tasks to stderr every {} tasks"#,
-        max_concurrent_tasks, config, timeout, report_tasks_step
+    --no-header ... will report remaining tasks to stderr every {} tasks
+    --list-aliases ... list the aliases defined in ~/.config/personal/execute-aliases.conf
+    --no-progress ... do not draw progress lines at the bottom of stderr [default: drawn if stderr is a terminal]
+    --deps ... run repos after the ones they depend on, `<path> after=<path|name>,...` in the config or the --deps-file, skip them if those fail
+    --allow-shell-env ... let --load-env source the file with `sh`, for `$(...)` and the like
+    --pick ... choose the paths to run on in a fuzzy finder, Tab marks several
+    --pick-last ... run on the paths picked last time for this config
+    --read-only ... run every task with a read-only view of the filesystem (user and mount namespaces)
+    --writable-tmp ... with --read-only, give every task an empty writable /tmp
+    --no-network ... run every task without network, only a loopback interface
+    --pty ... run each task under its own pseudo-terminal, tools colorize natively
+    --stdin-broadcast ... read stdin once and feed it to every task
+    --watch ... after the run, run again in every repo whose files change (ignores what `grep` excludes)
+    --tui ... browse tasks and their output interactively, filter, search and re-run them, tasks cannot read the terminal
+    --timings ... print the slowest tasks, total wall time and a histogram of task times"#,
+        max_concurrent_tasks,
+        config,
+        timeout,
+        timings_top,
+        deps::DEFAULT_DEPS_FILE,
+        history::DEFAULT_KEEP_RUNS,
+        report_tasks_step
     );
 
-    return rendered;
+    rendered
 }
 
-fn parse_args(report_tasks_step: usize) -> Result<Args, lexopt::Error> {
+fn parse_args(report_tasks_step: usize, home: &str) -> Result<Args, lexopt::Error> {
     use lexopt::prelude::*;
 
     let mut show_header = true;
@@ -67,9 +213,44 @@ fn parse_args(report_tasks_step: usize) -> Result<Args, lexopt::Error> {
     let timeout_default: u64 = 3; // seconds
     let mut max_concurrent_tasks: usize = 4;
     let mut config_filename: String = "repo.conf".to_string();
+    let mut output_dir: Option<PathBuf> = None;
+    let mut junit_file: Option<PathBuf> = None;
+    let mut timings = false;
+    let mut timings_top: usize = 10;
+    let mut schedule_by_history = false;
+    let mut start_interval = Duration::ZERO;
+    let mut max_starts_per_second: Option<f64> = None;
+    let mut per_host_limit: Option<usize> = None;
+    let mut max_load: Option<f64> = None;
+    let mut use_pty = false;
+    let mut pty_size: Option<pty::PtySize> = None;
+    let mut stdin_broadcast = false;
+    let mut batch_size: Option<usize> = None;
+    let mut max_args_bytes: Option<usize> = None;
+    let mut limits = Limits::default();
+    let mut tui = false;
+    let mut show_progress = true;
+    let mut history_keep_runs = Some(history::DEFAULT_KEEP_RUNS);
+    let mut history_max_bytes = history::DEFAULT_MAX_BYTES;
+    let mut watch = false;
+    let mut watch_debounce = Duration::from_millis(300);
+    let mut use_deps = false;
+    let mut deps_file = deps::DEFAULT_DEPS_FILE.to_string();
+    let mut matrix: Vec<Axis> = Vec::new();
+    let mut hooks = Hooks::default();
+    let mut load_env: Option<env_file::Format> = None;
+    let mut allow_shell_env = false;
+    let mut script: Option<Script> = None;
+    let mut pick: Option<Pick> = None;
+    let mut sandbox = Sandbox::default();
+    let mut shell: Option<String> = None;
     let mut command: Vec<String> = Vec::new();
 
-    let mut parser = lexopt::Parser::from_env();
+    let aliases_path = aliases::aliases_path(home);
+    let args = aliases::expand(std::env::args_os().skip(1).collect(), &aliases_path)?;
+    debug!("args: {:?}", args);
+
+    let mut parser = lexopt::Parser::from_args(args);
     while let Some(arg) = parser.next()? {
         match arg {
             Short('t') | Long("timeout") => {
@@ -77,7 +258,7 @@ fn parse_args(report_tasks_step: usize) -> Result<Args, lexopt::Error> {
             }
 
             Short('w') | Long("max-concurrent-tasks") => {
-                max_concurrent_tasks = parser.value()?.parse()?;
+                max_concurrent_tasks = parse_concurrency(&parser.value()?.string()?)?;
             }
 
             Long("no-header") => {
@@ -92,6 +273,224 @@ fn parse_args(report_tasks_step: usize) -> Result<Args, lexopt::Error> {
                 in_repos = false;
             }
 
+            Long("output-dir") => {
+                output_dir = Some(parser.value()?.into());
+            }
+
+            Long("junit") => {
+                junit_file = Some(parser.value()?.into());
+            }
+
+            Long("timings") => {
+                timings = true;
+            }
+
+            Long("timings-top") => {
+                timings_top = parser.value()?.parse()?;
+            }
+
+            Long("schedule") => {
+                let value = parser.value()?.string()?;
+                match value.as_str() {
+                    "config" => schedule_by_history = false,
+                    "history" => schedule_by_history = true,
+                    _ => return Err(format!("unknown schedule: {}", value).into()),
+                }
+            }
+
+            Long("start-interval") => {
+                start_interval = parse_duration(&parser.value()?.string()?)?;
+            }
+
+            Long("max-starts-per-second") => {
+                let value: f64 = parser.value()?.parse()?;
+                if !value.is_finite() || value <= 0.0 {
+                    return Err("--max-starts-per-second has to be a positive number".into());
+                }
+                max_starts_per_second = Some(value);
+            }
+
+            Long("per-host-limit") => {
+                let value: usize = parser.value()?.parse()?;
+                if value == 0 {
+                    return Err("--per-host-limit has to be at least 1".into());
+                }
+                per_host_limit = Some(value);
+            }
+
+            Long("max-load") => {
+                let value: f64 = parser.value()?.parse()?;
+                if !value.is_finite() || value <= 0.0 {
+                    return Err("--max-load has to be a positive number".into());
+                }
+                if throttle::pressure().is_none() {
+                    return Err("--max-load needs /proc/pressure (Linux 4.20+ with PSI)".into());
+                }
+                max_load = Some(value);
+            }
+
+            Long("pty") => {
+                use_pty = true;
+            }
+
+            Short('n') | Long("batch-size") => {
+                let value: usize = parser.value()?.parse()?;
+                if value == 0 {
+                    return Err("--batch-size has to be at least 1".into());
+                }
+                batch_size = Some(value);
+            }
+
+            Long("max-args-bytes") => {
+                max_args_bytes = Some(parser.value()?.parse()?);
+            }
+
+            Long("nice") => {
+                let value: i32 = parser.value()?.parse()?;
+                if !(-20..=19).contains(&value) {
+                    return Err("--nice has to be between -20 and 19".into());
+                }
+                limits.nice = Some(value);
+            }
+
+            Long("ionice") => {
+                limits.ionice = Some(limits::parse_ionice(&parser.value()?.string()?)?);
+            }
+
+            Long("rlimit-as") => {
+                limits.address_space = Some(limits::parse_bytes(&parser.value()?.string()?)?);
+            }
+
+            Long("rlimit-cpu") => {
+                let value = parse_duration(&parser.value()?.string()?)?;
+                // the kernel counts whole seconds
+                limits.cpu = Some(value.as_secs_f64().ceil().max(1.0) as u64);
+            }
+
+            Long("rlimit-nofile") => {
+                limits.open_files = Some(parser.value()?.parse()?);
+            }
+
+            Long("tui") => {
+                tui = true;
+            }
+
+            Long("list-aliases") => {
+                aliases::print_list(&aliases_path)?;
+                std::process::exit(0);
+            }
+
+            Long("watch") => {
+                if !cfg!(target_os = "linux") {
+                    return Err("--watch is only supported on Linux".into());
+                }
+                watch = true;
+            }
+
+            Long("watch-debounce") => {
+                watch_debounce = parse_duration(&parser.value()?.string()?)?;
+            }
+
+            Long("matrix") => {
+                let axis = matrix::parse_axis(&parser.value()?.string()?)?;
+                if matrix.iter().any(|other| other.name == axis.name) {
+                    return Err(format!("--matrix {} is given twice", axis.name).into());
+                }
+                matrix.push(axis);
+            }
+
+            Long("before-all") => {
+                hooks.before_all = Some(parser.value()?.string()?);
+            }
+
+            Long("after-all") => {
+                hooks.after_all = Some(parser.value()?.string()?);
+            }
+
+            Long("before-each") => {
+                hooks.before_each = Some(parser.value()?.string()?);
+            }
+
+            Long("after-each") => {
+                hooks.after_each = Some(parser.value()?.string()?);
+            }
+
+            Long("sh") => {
+                script = Some(Script::Inline(parser.value()?.string()?));
+            }
+
+            Long("sh-file") => {
+                script = Some(script::file(&parser.value()?.string()?)?);
+            }
+
+            Long("shell") => {
+                shell = Some(parser.value()?.string()?);
+            }
+
+            Long("read-only") => {
+                sandbox.read_only = true;
+            }
+
+            Long("writable-tmp") => {
+                sandbox.writable_tmp = true;
+            }
+
+            Long("no-network") => {
+                sandbox.no_network = true;
+            }
+
+            Long("pick") => {
+                pick = Some(Pick::Interactive);
+            }
+
+            Long("pick-last") => {
+                pick = Some(Pick::Last);
+            }
+
+            Long("load-env") => {
+                load_env = Some(env_file::parse_format(&parser.value()?.string()?)?);
+            }
+
+            Long("allow-shell-env") => {
+                allow_shell_env = true;
+            }
+
+            Long("deps") => {
+                use_deps = true;
+            }
+
+            Long("deps-file") => {
+                deps_file = parser.value()?.string()?;
+            }
+
+            Long("no-history") => {
+                history_keep_runs = None;
+            }
+
+            Long("history-keep") => {
+                let value: usize = parser.value()?.parse()?;
+                if value == 0 {
+                    return Err("--history-keep has to be at least 1, see --no-history".into());
+                }
+                history_keep_runs = Some(value);
+            }
+
+            Long("history-max-size") => {
+                history_max_bytes = limits::parse_bytes(&parser.value()?.string()?)?;
+            }
+
+            Long("no-progress") => {
+                show_progress = false;
+            }
+
+            Long("stdin-broadcast") => {
+                stdin_broadcast = true;
+            }
+
+            Long("pty-size") => {
+                pty_size = Some(pty::parse_size(&parser.value()?.string()?)?);
+            }
+
             Short('c') | Long("config") => {
                 if let Ok(value) = parser.value() {
                     let value_str = value.to_string_lossy();
@@ -104,6 +503,7 @@ fn parse_args(report_tasks_step: usize) -> Result<Args, lexopt::Error> {
                     config_filename,
                     timeout_default,
                     report_tasks_step,
+                    timings_top,
                 )
                 .into());
             }
@@ -115,7 +515,70 @@ fn parse_args(report_tasks_step: usize) -> Result<Args, lexopt::Error> {
         }
     }
 
-    if in_repos && timeout == None {
+    // both boil down to a minimum gap between two starts
+    if let Some(per_second) = max_starts_per_second {
+        let interval = Duration::try_from_secs_f64(1.0 / per_second)
+            .map_err(|_| "--max-starts-per-second is too small")?;
+        start_interval = start_interval.max(interval);
+    }
+
+    if in_repos && (batch_size.is_some() || max_args_bytes.is_some()) {
+        return Err("--batch-size and --max-args-bytes only work with --files".into());
+    }
+    // a byte limit alone packs as many files as fit
+    if batch_size.is_none() && max_args_bytes.is_some() {
+        batch_size = Some(usize::MAX);
+    }
+
+    if watch && !in_repos {
+        return Err("--watch only works with repos".into());
+    }
+    if watch && (tui || output_dir.is_some() || junit_file.is_some()) {
+        return Err("--watch cannot be combined with --tui, --output-dir or --junit".into());
+    }
+
+    if use_deps && !in_repos {
+        return Err("--deps only works with repos".into());
+    }
+    if use_deps && schedule_by_history {
+        return Err("--deps cannot be combined with --schedule history".into());
+    }
+
+    if !matrix.is_empty() && (use_deps || watch || batch_size.is_some()) {
+        return Err("--matrix cannot be combined with --deps, --watch or --batch-size".into());
+    }
+
+    if !sandbox.is_empty() && !cfg!(target_os = "linux") {
+        return Err("--read-only and --no-network are only supported on Linux".into());
+    }
+    if sandbox.writable_tmp && !sandbox.read_only {
+        return Err("--writable-tmp needs --read-only".into());
+    }
+
+    if pick.is_some() && use_deps {
+        return Err("--pick cannot be combined with --deps".into());
+    }
+
+    if load_env.is_some() && !in_repos {
+        return Err("--load-env only works with repos".into());
+    }
+    if allow_shell_env && load_env.is_none() {
+        return Err("--allow-shell-env needs --load-env".into());
+    }
+
+    if shell.is_some() && script.is_none() {
+        return Err("--shell needs --sh or --sh-file".into());
+    }
+    if script.is_some() && !command.is_empty() {
+        return Err("--sh and --sh-file replace the command, pass nothing after them".into());
+    }
+    let mut title = command.clone();
+    if let Some(script) = script.as_ref() {
+        command = script::command(script, script::shell(shell));
+        title = script::title(script);
+    }
+
+    if in_repos && timeout.is_none() {
         timeout = Some(Duration::from_secs(timeout_default));
     }
 
@@ -126,225 +589,176 @@ fn parse_args(report_tasks_step: usize) -> Result<Args, lexopt::Error> {
         config_filename: config_filename.clone(),
         max_concurrent_tasks,
         timeout,
+        output_dir,
+        junit_file,
+        timings_top: if timings { Some(timings_top) } else { None },
+        schedule_by_history,
+        start_interval,
+        per_host_limit,
+        max_load,
+        pty_size: if use_pty {
+            Some(pty_size.unwrap_or_else(pty::terminal_size))
+        } else {
+            None
+        },
+        stdin_broadcast,
+        batch_size: batch_size.unwrap_or(1),
+        max_args_bytes,
+        limits,
+        tui,
+        show_progress: show_progress && !tui && std::io::stderr().is_terminal(),
+        history_keep_runs,
+        history_max_bytes,
+        watch: if watch { Some(watch_debounce) } else { None },
+        deps_file: if use_deps { Some(deps_file) } else { None },
+        matrix,
+        hooks,
+        load_env: load_env.map(|format| LoadEnv {
+            format,
+            allow_shell: allow_shell_env,
+        }),
         command: if command.is_empty() {
             return Err(get_usage_info(
                 max_concurrent_tasks,
                 config_filename.clone(),
                 timeout_default,
                 report_tasks_step,
+                timings_top,
             )
             .into());
         } else {
             command
         },
+        pick,
+        sandbox,
+        is_script: script.is_some(),
+        title,
     })
 }
 
-async fn collect_lines_poll_once<T: futures_lite::AsyncBufRead + Unpin>(
-    lines: &mut futures_lite::io::Lines<T>,
-) -> String {
-    let mut buf = Vec::new();
-    loop {
-        match smol::future::poll_once(lines.next()).await {
-            Some(Some(Ok(line))) => buf.push(line),
-            Some(Some(Err(err))) => {
-                debug!("Error reading line: {:?}", err);
-                break;
-            }
-            Some(None) | None => break, // Stream ended or nothing available
-        }
-    }
-    buf.join("\n")
-}
+// `8`, `auto` (one per CPU) or `150%` (of the CPUs)
+// more workers than this only wait on each other
+const MAX_CONCURRENT_TASKS: usize = 1024;
 
-async fn run_command(
-    cmd: String,
-    arguments: Vec<String>,
-    file: PathBuf,
-    use_color: bool,
-    in_repos: bool,
-    timeout: Option<Duration>,
-) -> Result<(String, Option<i32>, String, String), String> {
-    let mut args = arguments.clone();
-
-    let mut idx: usize = 0;
-    if use_color {
-        if cmd == "git" {
-            args.insert(idx, "-c".to_string());
-            idx += 1;
-            args.insert(idx, "color.status=always".to_string());
-            idx += 1;
-        } else if cmd == "grep" {
-            args.insert(idx, "--color=always".to_string());
-            idx += 1;
+fn parse_concurrency(value: &str) -> Result<usize, String> {
+    let cpus = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let workers = if value == "auto" {
+        cpus
+    } else if let Some(percent) = value.strip_suffix('%') {
+        let percent: f64 = percent
+            .parse()
+            .map_err(|_| format!("invalid percentage: {:?}", value))?;
+        if !percent.is_finite() || percent <= 0.0 {
+            return Err(format!("invalid percentage: {:?}", value));
         }
-    }
-    if cmd == "grep" {
-        args.insert(idx, "--exclude-dir=.git".to_string());
-        idx += 1;
-        args.insert(idx, "--exclude-dir=.helm".to_string());
-        idx += 1;
-        args.insert(idx, "--exclude-dir=.tox".to_string());
-        idx += 1;
-        args.insert(idx, "--exclude-dir=.pulumi".to_string());
-        idx += 1;
-        args.insert(idx, "--exclude-dir=.cache".to_string());
-        idx += 1;
-        args.insert(idx, "--exclude-dir=.mypy_cache".to_string());
-        idx += 1;
-        args.insert(idx, "--exclude-dir=.eggs".to_string());
-        idx += 1;
-        args.insert(idx, "--exclude-dir=*.egg-info".to_string());
-        idx += 1;
-        args.insert(idx, "--exclude-dir=*venv*".to_string());
-        idx += 1;
-        args.insert(idx, "--exclude-dir=_build".to_string());
-        idx += 1;
-        args.insert(idx, "--exclude-dir=__pycache__".to_string());
-        idx += 1;
-        args.insert(idx, "--exclude-dir=.ruff_cache".to_string());
-        idx += 1;
-        args.insert(idx, "--exclude=\"*.pyc\"".to_string());
-        idx += 1;
-        args.insert(idx, "--exclude-dir=.pytest_cache".to_string());
-        idx += 1;
-        args.insert(idx, "--exclude=poetry.lock".to_string());
-        idx += 1;
-        args.insert(idx, "--exclude-dir=htmlcov".to_string());
-        idx += 1;
-        args.insert(idx, "--exclude=\"*.html\"".to_string());
-        idx += 1;
-        args.insert(idx, "--exclude=build.*trace".to_string());
-        idx += 1;
-        args.insert(idx, "--exclude=Session.vim".to_string());
-    }
-
-    let mut command = Command::new(cmd.clone());
-    if in_repos {
-        command.current_dir(file.clone());
+        (cpus as f64 * percent / 100.0).ceil() as usize
     } else {
-        args.push(file.to_string_lossy().to_string());
-    }
-
-    command.stdout(Stdio::piped());
-    command.stderr(Stdio::piped());
-
-    let mut err_info = format!(
-        "Spawn failed in '{}'. Cmd: {:?}, Args: {:?}",
-        file.to_string_lossy().into_owned(),
-        cmd,
-        args.clone(),
-    );
-    if !in_repos {
-        err_info = format!(
-            "Spawn failed (--files): Cmd: {:?}, Args: {:?}",
-            cmd,
-            args.clone(),
-        );
-    }
-    let mut child = command
-        .args(args)
-        .spawn()
-        .map_err(|e| format!("{}: {}", err_info, e))?;
-
-    let mut stdout = BufReader::new(child.stdout.take().unwrap()).lines();
-    let mut stderr = BufReader::new(child.stderr.take().unwrap()).lines();
-
-    let task = async {
-        let status = child
-            .status()
-            .await
-            .map_err(|e| format!("Wait failed for '{:?}': {}", file, e))?;
-        let fname = file.to_string_lossy().into_owned();
-
-        let stdout_str = collect_lines_poll_once(&mut stdout).await;
-        let stderr_str = collect_lines_poll_once(&mut stderr).await;
-
-        Ok((fname, status.code(), stdout_str, stderr_str))
+        value
+            .parse()
+            .map_err(|_| format!("invalid number of tasks: {:?}", value))?
     };
-
-    if let Some(to) = timeout {
-        if let Some(res) = task.timeout(to).await {
-            res
-        } else {
-            // Kill the process (best effort)
-            let _ = child.kill();
-            let dir = file.to_string_lossy().into_owned();
-
-            let stdout_str = collect_lines_poll_once(&mut stdout).await;
-            let stderr_str = collect_lines_poll_once(&mut stderr).await;
-
-            let mut stderr_display = "".to_string();
-            if stderr_str.len() > 0 {
-                stderr_display = format!("\n[.] stderr:\n{}", stderr_str);
-            }
-            Err(format!(
-                "Timed out in '{}' after {:?}.\n{}{}",
-                dir, to, stdout_str, stderr_display
-            ))
-        }
-    } else {
-        // this condition does not use a timeout
-        task.await
+    if workers == 0 {
+        return Err(format!("{:?} results in 0 concurrent tasks", value));
     }
+    Ok(workers.min(MAX_CONCURRENT_TASKS))
 }
 
-fn get_paths(config_filename: String, home: String) -> Vec<String> {
-    let mut paths: Vec<String> = Vec::new();
-
-    let mut config_path = PathBuf::from(config_filename.as_str());
-    if !config_path.is_absolute() {
-        debug!("config_path: {:?} is not absolute.", config_path);
-        config_path =
-            PathBuf::from(format!("{}/{}/{}", home, ".config/personal", config_filename).as_str());
-        debug!("updated config_path: {:?}", config_path);
+// `200ms`, `1.5s`, `2m` or plain seconds
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split_at = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split_at);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration: {:?}", value))?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return Err(format!("invalid duration unit: {:?}", value)),
+    };
+    if !seconds.is_finite() {
+        return Err(format!("duration too long: {:?}", value));
     }
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("duration too long: {:?}", value))
+}
 
-    let config = fs::read_to_string(config_path);
-    let lines: Vec<String> = config
-        .expect("new lines missing")
-        .lines()
-        .map(|line| line.to_string())
-        .collect();
+enum Next {
+    Done(Option<Box<TaskResult>>),
+    AllDone,
+    Interrupted(i32),
+    Rerun(Box<TaskResult>),
+    Tui(tui::Action),
+    Redraw,
+}
 
-    for line in lines {
-        if line.starts_with("#") || line.trim().len() < 1 {
-            continue;
+fn print_result(result: &TaskResult, show_header: bool) {
+    let mut exit_info = "".to_string();
+    match &result.outcome {
+        Outcome::Exited(0) => {}
+        Outcome::Exited(ec) => {
+            exit_info = format!("[-] Non-zero {}: ", ec);
         }
-
-        let shell_expanded: String;
-        if let Ok(sh_expanded) = full(&line) {
-            shell_expanded = sh_expanded.into_owned();
-        } else {
-            shell_expanded = line;
+        Outcome::Signaled(sig) => {
+            exit_info = format!("[-] Signal {}: ", sig);
         }
-        debug!("shell_expanded: {}", shell_expanded);
-
-        if !shell_expanded.contains("*") && !shell_expanded.contains("{") {
-            paths.push(shell_expanded.clone());
-            continue;
+        Outcome::LimitExceeded(sig, reason) => {
+            exit_info = format!("[-] Signal {} ({}): ", sig, reason);
         }
-
-        let brace_expanded = brace_expand(&shell_expanded);
-        debug!("brace_expanded: {:?}", brace_expanded);
-
-        let mut glob_expanded: Vec<String> = Vec::new();
-        for expanded in brace_expanded {
-            let mut globbed: Vec<String> = glob(&expanded)
-                .expect("Glob failed")
-                .map(|item| item.expect("Error on path in glob").display().to_string())
-                .collect();
-
-            glob_expanded.append(&mut globbed);
+        Outcome::TimedOut(to) => {
+            let mut stderr_display = "".to_string();
+            if !result.stderr.is_empty() {
+                stderr_display = format!("\n[.] stderr:\n{}", result.stderr);
+            }
+            eprintln!(
+                "--\n! Timed out in {} after {:?}.\n{}{}",
+                result.label(),
+                to,
+                result.stdout,
+                stderr_display
+            );
+            return;
         }
-        debug!("glob_expanded: {:?}", glob_expanded);
-
-        for path in glob_expanded {
-            paths.push(path);
+        Outcome::SpawnFailed(err) | Outcome::WaitFailed(err) | Outcome::SandboxFailed(err) => {
+            eprintln!("--\n! {}", err);
+            return;
+        }
+        Outcome::Skipped(reason) => {
+            // what a vetoing --before-each said
+            let mut output = "".to_string();
+            if !result.stdout.is_empty() {
+                output = format!("\n{}", result.stdout);
+            }
+            if !result.stderr.is_empty() {
+                output = format!("{}\n[.] stderr:\n{}", output, result.stderr);
+            }
+            eprintln!("--\n! Skipped {}, {}.{}", result.label(), reason, output);
+            return;
         }
     }
-    debug!("paths: {:?}", paths);
-    paths
+
+    let mut header = format!("--\n{}{}\n", exit_info, result.label());
+    if !show_header {
+        header = "".to_string();
+    }
+
+    let mut stderr_display = "".to_string();
+    if !result.stderr.is_empty() {
+        stderr_display = format!("\n[.] stderr:\n{}", result.stderr);
+    }
+    let is_no_output = result.stdout.is_empty() && result.stderr.is_empty();
+    // a task killed for its limits rarely gets to say anything itself
+    let is_limit = matches!(result.outcome, Outcome::LimitExceeded(_, _));
+    if !is_no_output {
+        println!("{}{}{}", header, result.stdout, stderr_display);
+    } else if is_limit && show_header {
+        println!("{}", header.trim_end());
+    }
 }
 
 fn main() -> Result<(), lexopt::Error> {
@@ -355,101 +769,539 @@ fn main() -> Result<(), lexopt::Error> {
     // if no_show_header
     let report_tasks_step: usize = 10;
 
-    let args = parse_args(report_tasks_step)?;
+    if std::env::args_os()
+        .nth(1)
+        .is_some_and(|arg| arg == "history")
+    {
+        return history::command(
+            std::env::args_os().skip(2).collect(),
+            &store::data_dir(&home),
+        );
+    }
+
+    let args = parse_args(report_tasks_step, &home)?;
     let show_header = args.show_header;
     let use_color = args.use_color;
     let in_repos = args.in_repos;
     let max_concurrent_tasks = args.max_concurrent_tasks;
     let timeout = args.timeout;
     let command = args.command;
+    let title = args.title;
     let config_filename = args.config_filename;
 
     log_info!("config file: {:}", config_filename);
     log_info!("number of concurrent tasks: {}", max_concurrent_tasks);
 
-    let paths = get_paths(config_filename, home);
+    let config_path = config::config_path(&config_filename, &home);
+    // chosen before anything runs
+    let mut picked: Option<Vec<config::Entry>> = None;
+    if let Some(pick) = args.pick {
+        let entries = pick::entries(pick, &config_path, &store::data_dir(&home))?;
+        log_info!("picked {} of the paths", entries.len());
+        picked = Some(entries);
+    }
+
+    let started = chrono::Local::now();
+    if let Some(hook) = args.hooks.before_all.as_deref() {
+        hooks::before_all(hook)?;
+    }
+
+    let mut output_dir: Option<OutputDir> = None;
+    if let Some(dir) = args.output_dir {
+        match OutputDir::create(&dir) {
+            Ok(out) => output_dir = Some(out),
+            Err(e) => {
+                return Err(format!("cannot create output dir {:?}: {}", dir, e).into());
+            }
+        }
+    }
+
+    let mut junit = args.junit_file.as_deref().map(JunitReport::new);
+
+    let mut timings: Option<Timings> = None;
+    if args.timings_top.is_some() {
+        timings = Some(Timings::new());
+    }
+
+    let data_dir = store::data_dir(&home);
+    let mut durations = DurationStore::load(data_dir.clone());
+
+    let mut history: Option<HistoryWriter> = None;
+    if args.history_keep_runs.is_some() {
+        let argv: Vec<String> = std::env::args().collect();
+        match HistoryWriter::create(&data_dir, &argv, &title, &config_filename) {
+            Ok(writer) => history = Some(writer),
+            Err(e) => log_err!("cannot record this run in the history: {}", e),
+        }
+    }
+    let command_key = title.join(" ");
 
     let mut name = "files".to_string();
     if in_repos {
         name = "repos".to_string();
     }
-    let number_of_paths = paths.len();
-    log_info!("number of {}: {}", name, number_of_paths);
     if let Some(timeout) = timeout {
         log_info!("timeout: {:?}", timeout);
     }
 
-    let cmd = command[0].clone();
-    let mut cmd_args: Vec<String> = Vec::new();
+    let mut batcher: Option<Batcher> = None;
+    if args.batch_size > 1 {
+        let max_args_bytes = args
+            .max_args_bytes
+            .unwrap_or_else(batch::default_max_args_bytes);
+        batcher = Some(Batcher::new(&command, args.batch_size, max_args_bytes));
+    }
 
-    for arg in command.clone().split_off(1) {
-        cmd_args.push(arg);
+    let progress = Arc::new(Progress::default());
+    let lookup_hosts = args.per_host_limit.is_some();
+    let mut events_sender = None;
+    let mut events = None;
+    if args.tui || args.show_progress {
+        let (sender, receiver) = smol::channel::unbounded();
+        events_sender = Some(sender);
+        events = Some(receiver);
     }
 
-    smol::block_on(async {
-        let mut tasks = FuturesUnordered::new();
-        let semaphore = Arc::new(Semaphore::new(max_concurrent_tasks));
+    let mut graph: Option<deps::Graph> = None;
+    let mut jobs = None;
+    if let Some(deps_file) = args.deps_file.as_deref() {
+        // the order needs every path up front
+        let entries = config::get_entries(&config_path)?;
+        graph = Some(
+            deps::Graph::build(entries, deps_file)
+                .map_err(|e| format!("invalid dependencies in {:?}: {}", config_path, e))?,
+        );
+    } else if args.schedule_by_history {
+        // sorting needs every path up front
+        let mut entries = match picked.clone() {
+            Some(entries) => entries,
+            None => config::get_entries(&config_path)?,
+        };
+        durations.sort_longest_first(&mut entries, &command_key);
+        debug!(
+            "paths by expected duration: {:?}",
+            entries.iter().map(|entry| &entry.path).collect::<Vec<_>>()
+        );
+        jobs = Some(pool::produce(
+            entries.into_iter(),
+            matrix::combinations(&args.matrix),
+            batcher,
+            lookup_hosts,
+            progress.clone(),
+            events_sender.clone(),
+        ));
+    } else {
+        let entries: Box<dyn Iterator<Item = config::Entry> + Send> = match picked.clone() {
+            Some(entries) => Box::new(entries.into_iter()),
+            None => Box::new(
+                config::stream_entries(&config_path)
+                    .map_err(|e| format!("cannot read config {:?}: {}", config_path, e))?,
+            ),
+        };
+        jobs = Some(pool::produce(
+            entries,
+            matrix::combinations(&args.matrix),
+            batcher,
+            lookup_hosts,
+            progress.clone(),
+            events_sender.clone(),
+        ));
+    }
 
-        for file in paths {
-            let sem_clone = semaphore.clone();
-            let cmd_clone = cmd.clone();
-            let cmd_args_clone = cmd_args.clone();
+    let mut stdin_source: Option<Arc<StdinSource>> = None;
+    if args.stdin_broadcast {
+        match StdinSource::read() {
+            Ok(source) => {
+                log_info!("stdin for every task: {} bytes", source.len());
+                stdin_source = Some(Arc::new(source));
+            }
+            Err(e) => return Err(format!("cannot read stdin: {}", e).into()),
+        }
+    }
 
-            tasks.push(smol::spawn(async move {
-                // will be release when it goes out of scope
-                let _permit = sem_clone.acquire().await;
+    let run_options = Arc::new(RunOptions {
+        use_color,
+        in_repos,
+        timeout,
+        pty_size: args.pty_size,
+        stdin: stdin_source,
+        limits: args.limits,
+        load_env: args.load_env,
+        is_script: args.is_script,
+        sandbox: args.sandbox,
+        // the TUI has the terminal to itself
+        foreground: !args.tui && interrupt::in_foreground(),
+    });
 
-                let result = run_command(
-                    cmd_clone,
-                    cmd_args_clone,
-                    file.clone().into(),
-                    use_color,
-                    in_repos,
-                    timeout,
-                )
-                .await;
+    let pool = Arc::new(Pool {
+        workers: max_concurrent_tasks,
+        hosts: args
+            .per_host_limit
+            .map(|limit| Arc::new(HostLimits::new(limit))),
+        start_gate: Arc::new(StartGate::new(args.start_interval)),
+        load_gate: args
+            .max_load
+            .map(|max_load| Arc::new(LoadGate::new(max_load))),
+        options: run_options,
+        cmd: command[0].clone(),
+        cmd_args: command[1..].to_vec(),
+        events: events_sender.clone(),
+        hooks: args.hooks.clone(),
+    });
 
-                result
-            }));
+    let mut tui: Option<Tui> = None;
+    let mut progress_line: Option<ProgressLine> = None;
+    if let Some(events) = events {
+        if args.tui {
+            match Tui::start(&title, events) {
+                Ok(started) => tui = Some(started),
+                Err(e) => return Err(format!("cannot start --tui: {}", e).into()),
+            }
+        } else {
+            progress_line = Some(ProgressLine::new(progress.clone(), events));
         }
+    }
+
+    let interrupted_by = smol::block_on(async {
+        let results = match (graph, jobs) {
+            (Some(graph), _) => {
+                deps::start(graph, &pool, lookup_hosts, progress.clone(), events_sender)
+            }
+            (None, Some(jobs)) => pool.start(jobs),
+            (None, None) => unreachable!("jobs are produced without --deps"),
+        };
+        // --tui re-runs, their results only go to the TUI
+        let (rerun_sender, reruns) = smol::channel::unbounded();
+        let mut all_done = false;
+
+        let mut signals = match Signals::new([Signal::Int, Signal::Term]) {
+            Ok(signals) => Some(signals),
+            Err(e) => {
+                log_err!("cannot install signal handlers: {}", e);
+                None
+            }
+        };
+        let mut interrupted_by: Option<i32> = None;
+        let mut not_started: usize = 0;
 
         let mut tasks_done: usize = 0;
-        while let Some(result) = tasks.next().await {
-            if let Ok((file, exit_code, stdout, stderr)) = result {
-                let mut exit_info = "".to_string();
-                let ec = exit_code.unwrap();
-                if ec != 0 {
-                    exit_info = format!("[-] Non-zero {}: ", ec.to_string());
-                }
-                let mut header = format!("--\n{}'{}'\n", exit_info, file.as_str());
-                if !show_header {
-                    header = "".to_string();
+        let mut logged_totals = false;
+        let log_totals = || {
+            log_info!(
+                "number of {}: {}",
+                name,
+                progress.paths.load(Ordering::SeqCst)
+            );
+            let number_of_jobs = progress.jobs.load(Ordering::SeqCst);
+            if !args.matrix.is_empty() {
+                log_info!("number of tasks: {}", number_of_jobs);
+            } else if number_of_jobs != progress.paths.load(Ordering::SeqCst) {
+                log_info!("number of batches: {}", number_of_jobs);
+            }
+        };
+        let mut matrix_summary: Option<matrix::Summary> = None;
+        if !args.matrix.is_empty() {
+            matrix_summary = Some(matrix::Summary::new(&args.matrix));
+        }
+        let mut failed: Vec<(String, &'static str, PathBuf)> = Vec::new();
+        // for --after-all
+        let mut summary_tasks: Option<Vec<serde_json::Value>> = None;
+        if args.hooks.after_all.is_some() {
+            summary_tasks = Some(Vec::new());
+        }
+        // what --watch starts out showing
+        let mut latest: Option<HashMap<String, Box<TaskResult>>> = None;
+        if args.watch.is_some() {
+            latest = Some(HashMap::new());
+        }
+        loop {
+            // the TUI stays open to browse the results
+            if all_done && tui.is_none() {
+                break;
+            }
+            // paths are streamed, we know how many there are once all are read
+            if !logged_totals && tui.is_none() && progress.complete.load(Ordering::SeqCst) {
+                if let Some(line) = progress_line.as_mut() {
+                    line.clear();
                 }
+                log_totals();
+                logged_totals = true;
+            }
 
-                let mut stderr_display = "".to_string();
-                let std_err_len = stderr.len();
-                if std_err_len > 0 {
-                    stderr_display = format!("\n[.] stderr:\n{}", stderr);
+            let next_result = async {
+                if all_done {
+                    std::future::pending().await
+                }
+                match results.recv().await {
+                    Ok(result) => Next::Done(result.map(Box::new)),
+                    Err(_) => Next::AllDone,
                 }
-                let is_no_output = stdout.len() <= 0 && std_err_len <= 0;
-                if !is_no_output {
-                    println!("{}{}{}", header, stdout, stderr_display);
+            };
+            let next_signal = async {
+                if let Some(signals) = signals.as_mut()
+                    && let Some(Ok(signal)) = signals.next().await
+                {
+                    return Next::Interrupted(signal as i32);
                 }
-            } else if let Err(err) = result {
-                eprintln!("--\n! {}", err);
+                std::future::pending().await
+            };
+            let next_rerun = async {
+                match reruns.recv().await {
+                    Ok(result) => Next::Rerun(Box::new(result)),
+                    Err(_) => std::future::pending().await,
+                }
+            };
+            let next_input = async {
+                match tui.as_mut() {
+                    Some(tui) => Next::Tui(tui.next().await),
+                    None => std::future::pending().await,
+                }
+            };
+            let next_redraw = async {
+                match progress_line.as_mut() {
+                    Some(line) => {
+                        line.next().await;
+                        Next::Redraw
+                    }
+                    None => std::future::pending().await,
+                }
+            };
+            let mut next = next_result
+                .or(next_signal)
+                .or(next_rerun)
+                .or(next_input)
+                .or(next_redraw)
+                .await;
+            if let Next::Redraw = next {
+                continue;
             }
+            // anything we print goes where the progress lines are
+            if let Some(line) = progress_line.as_mut() {
+                line.clear();
+            }
+            if let Next::Tui(tui::Action::Quit) = next {
+                // leaving the TUI early stops the run like Ctrl-C
+                if !all_done {
+                    next = Next::Interrupted(libc::SIGINT);
+                }
+                tui = None;
+            }
+            let result = match next {
+                Next::Done(result) => result,
+                Next::AllDone => {
+                    all_done = true;
+                    progress_line = None;
+                    if let Some(tui) = tui.as_mut() {
+                        tui.all_done();
+                    }
+                    continue;
+                }
+                Next::Rerun(result) => {
+                    if let Some(tui) = tui.as_mut() {
+                        tui.finished(*result);
+                    }
+                    continue;
+                }
+                Next::Tui(tui::Action::Rerun(job)) => {
+                    let rerun = pool.rerun(job);
+                    let rerun_sender = rerun_sender.clone();
+                    smol::spawn(async move {
+                        let _ = rerun_sender.send(rerun.await).await;
+                    })
+                    .detach();
+                    continue;
+                }
+                Next::Tui(_) | Next::Redraw => continue,
+                Next::Interrupted(signal) => {
+                    // messages below would end up in the TUI
+                    tui = None;
+                    if interrupted_by.is_some() {
+                        let killed = interrupt::signal_all(libc::SIGKILL);
+                        log_err!("interrupted again, killed {} running tasks", killed);
+                        std::process::exit(128 + signal);
+                    }
+                    interrupted_by = Some(signal);
+                    interrupt::request_stop();
+                    let forwarded = interrupt::signal_all(signal);
+                    log_err!(
+                        "interrupted, forwarded signal {} to {} running tasks, interrupt again to kill them",
+                        signal,
+                        forwarded
+                    );
+                    continue;
+                }
+            };
+            let Some(result) = result else {
+                not_started += 1;
+                continue;
+            };
 
-            if !show_header {
-                tasks_done += 1;
-                if tasks_done % report_tasks_step == 0 {
-                    let mut remaining_tasks = 0;
-                    if number_of_paths > 0 {
-                        remaining_tasks = number_of_paths - tasks_done;
+            if let Some(writer) = history.as_mut()
+                && let Err(e) = writer.record(&result)
+            {
+                log_err!("cannot record {} in the history: {}", result.label(), e);
+                history = None;
+            }
+            if let Some(report) = junit.as_mut() {
+                report.add(&result, &command_key);
+            }
+            if let Some(timings) = timings.as_mut()
+                && !matches!(result.outcome, Outcome::Skipped(_))
+            {
+                timings.add(&result);
+            }
+            if let Outcome::Exited(_) | Outcome::Signaled(_) | Outcome::TimedOut(_) = result.outcome
+                && interrupted_by.is_none()
+                && result.paths.len() == 1
+                && result.params.is_empty()
+            {
+                durations.record(&result.path, &command_key, result.duration);
+            }
+            if let Some(summary) = matrix_summary.as_mut() {
+                summary.add(&result);
+            }
+            if let Some(tasks) = summary_tasks.as_mut() {
+                tasks.push(result.to_json());
+            }
+            if let Some(out) = output_dir.as_mut() {
+                match out.write_task(&result) {
+                    Ok(task_dir) => {
+                        if !result.outcome.is_success() {
+                            failed.push((result.label(), result.outcome.kind(), task_dir));
+                        }
+                    }
+                    Err(e) => {
+                        log_err!("cannot write logs for {}: {}", result.label(), e);
+                        if tui.is_none() {
+                            print_result(&result, show_header);
+                        }
                     }
-                    log_info!("remaining tasks: {}", remaining_tasks);
                 }
+            } else if tui.is_none() {
+                print_result(&result, show_header);
+            }
+
+            tasks_done += 1;
+            if let Some(line) = progress_line.as_mut() {
+                line.finished(&result);
+            }
+            if tui.is_none() && !show_header && tasks_done.is_multiple_of(report_tasks_step) {
+                let number_of_jobs = progress.jobs.load(Ordering::SeqCst);
+                if progress.complete.load(Ordering::SeqCst) {
+                    log_info!("remaining tasks: {}", number_of_jobs - tasks_done);
+                } else {
+                    log_info!("remaining tasks: at least {}", number_of_jobs - tasks_done);
+                }
+            }
+            if let Some(tui) = tui.as_mut() {
+                tui.finished(*result);
+            } else if let Some(latest) = latest.as_mut() {
+                latest.insert(result.path.clone(), result);
+            }
+        }
+        if !logged_totals {
+            log_totals();
+        }
+
+        if let Some(report) = junit.as_ref()
+            && let Err(e) = report.write()
+        {
+            log_err!("cannot write JUnit report: {}", e);
+        }
+
+        if let Some(out) = output_dir.as_ref() {
+            if let Err(e) = out.write_index(&title, &config_filename) {
+                log_err!("cannot write index: {}", e);
+            }
+            for (path, kind, task_dir) in &failed {
+                eprintln!("[-] {}: {} -> {}", kind, path, task_dir.display());
+            }
+            log_info!(
+                "{} ok, {} failed, logs in {}",
+                tasks_done - failed.len(),
+                failed.len(),
+                out.dir().display()
+            );
+        }
+
+        if let Some(writer) = history.take() {
+            if let Err(e) = writer.finish(interrupted_by) {
+                log_err!("cannot record this run in the history: {}", e);
+            }
+            if let Some(keep_runs) = args.history_keep_runs
+                && let Err(e) = history::prune(&data_dir, keep_runs, args.history_max_bytes)
+            {
+                log_err!("cannot prune the history: {}", e);
+            }
+        }
+
+        if let Err(e) = durations.save() {
+            log_err!("cannot save task durations: {}", e);
+        }
+
+        if let (Some(timings), Some(top)) = (timings.as_mut(), args.timings_top) {
+            timings.print(max_concurrent_tasks, top);
+        }
+
+        if let Some(summary) = matrix_summary.as_ref() {
+            summary.print();
+        }
+
+        if interrupted_by.is_some() {
+            log_info!(
+                "interrupted: {} tasks finished, {} not started",
+                tasks_done,
+                not_started
+            );
+        }
+
+        if let (Some(hook), Some(tasks)) = (args.hooks.after_all.as_deref(), summary_tasks) {
+            let ok = tasks.iter().filter(|task| task["outcome"] == "ok").count();
+            let summary = serde_json::json!({
+                "command": title,
+                "config": config_filename,
+                "started": started.to_rfc3339(),
+                "finished": chrono::Local::now().to_rfc3339(),
+                "interrupted_by": interrupted_by,
+                "ok": ok,
+                "failed": tasks.len() - ok,
+                "not_started": not_started,
+                "tasks": tasks,
+            });
+            if let Err(e) = hooks::after_all(hook, &summary) {
+                log_err!("{}", e);
+            }
+        }
+
+        if let Some(debounce) = args.watch
+            && interrupted_by.is_none()
+        {
+            let watched = match picked.map_or_else(|| config::get_entries(&config_path), Ok) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    log_err!("{}", e);
+                    return interrupted_by;
+                }
+            };
+            match watch::run(
+                &pool,
+                watched,
+                latest.unwrap_or_default(),
+                debounce,
+                show_header,
+                signals.as_mut(),
+            )
+            .await
+            {
+                Ok(signal) => interrupted_by = Some(signal),
+                Err(e) => log_err!("{}", e),
             }
         }
+        interrupted_by
     });
+
+    if let Some(signal) = interrupted_by {
+        std::process::exit(128 + signal);
+    }
     Ok(())
 }
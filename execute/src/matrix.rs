@@ -0,0 +1,150 @@
+use crate::log_info;
+use crate::task::TaskResult;
+
+// The matrix values of one task, `[(PYTHON, 3.12), (CONTEXT, prod)]`.
+pub type Params = Vec<(String, String)>;
+
+// `--matrix PYTHON=3.11,3.12`
+pub struct Axis {
+    pub name: String,
+    pub values: Vec<String>,
+}
+
+pub fn parse_axis(value: &str) -> Result<Axis, String> {
+    let Some((name, values)) = value.split_once('=') else {
+        return Err(format!("expected --matrix NAME=v1,v2,..., got {:?}", value));
+    };
+    // it ends up in the environment of every task
+    let is_valid_name = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_valid_name {
+        return Err(format!(
+            "invalid --matrix name {:?}, use letters, digits and `_`",
+            name
+        ));
+    }
+    let values: Vec<String> = values
+        .split(',')
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+        .collect();
+    if values.is_empty() {
+        return Err(format!("--matrix {} has no values", name));
+    }
+    Ok(Axis {
+        name: name.to_string(),
+        values,
+    })
+}
+
+// Every combination of one value per axis, the first axis varies slowest.
+// No axes is one combination without values.
+pub fn combinations(axes: &[Axis]) -> Vec<Params> {
+    let mut combinations: Vec<Params> = vec![Vec::new()];
+    for axis in axes {
+        combinations = combinations
+            .iter()
+            .flat_map(|params| {
+                axis.values.iter().map(move |value| {
+                    let mut params = params.clone();
+                    params.push((axis.name.clone(), value.clone()));
+                    params
+                })
+            })
+            .collect();
+    }
+    combinations
+}
+
+// `{NAME}` -> the task's value, other braces stay as they are
+pub fn substitute(arg: &str, params: &Params) -> String {
+    let mut arg = arg.to_string();
+    for (name, value) in params {
+        arg = arg.replace(&format!("{{{}}}", name), value);
+    }
+    arg
+}
+
+// ` [PYTHON=3.12 CONTEXT=prod]`, nothing without a matrix
+pub fn label(params: &Params) -> String {
+    if params.is_empty() {
+        return String::new();
+    }
+    let params: Vec<String> = params
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    format!(" [{}]", params.join(" "))
+}
+
+struct Count {
+    value: String,
+    ok: usize,
+    failed: usize,
+}
+
+// ok and failed tasks per value of every axis
+pub struct Summary {
+    // axes and values in --matrix order
+    axes: Vec<(String, Vec<Count>)>,
+}
+
+impl Summary {
+    pub fn new(axes: &[Axis]) -> Summary {
+        Summary {
+            axes: axes
+                .iter()
+                .map(|axis| {
+                    let counts = axis
+                        .values
+                        .iter()
+                        .map(|value| Count {
+                            value: value.clone(),
+                            ok: 0,
+                            failed: 0,
+                        })
+                        .collect();
+                    (axis.name.clone(), counts)
+                })
+                .collect(),
+        }
+    }
+
+    pub fn add(&mut self, result: &TaskResult) {
+        for (name, value) in &result.params {
+            let Some((_, counts)) = self.axes.iter_mut().find(|(axis, _)| axis == name) else {
+                continue;
+            };
+            if let Some(count) = counts.iter_mut().find(|count| &count.value == value) {
+                if result.outcome.is_success() {
+                    count.ok += 1;
+                } else {
+                    count.failed += 1;
+                }
+            }
+        }
+    }
+
+    pub fn print(&self) {
+        for (name, counts) in &self.axes {
+            let width = counts
+                .iter()
+                .map(|count| count.value.len())
+                .max()
+                .unwrap_or(0);
+            log_info!("matrix {}:", name);
+            for count in counts {
+                log_info!(
+                    "  {:width$}  {} ok, {} failed",
+                    count.value,
+                    count.ok,
+                    count.failed,
+                    width = width
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+
+use crate::task::TaskResult;
+
+// `/home/me/Repos/a b` -> `home_me_Repos_a_b`
+fn sanitize(path: &str) -> String {
+    let mut name: String = path
+        .trim_matches('/')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.is_empty() {
+        name = "root".to_string();
+    }
+    name
+}
+
+// a task directory of an earlier run: `0003-<name>` with a `meta.json`
+fn is_task_dir(entry: &fs::DirEntry) -> bool {
+    let name = entry.file_name();
+    let Some((number, _)) = name.to_str().and_then(|name| name.split_once('-')) else {
+        return false;
+    };
+    !number.is_empty()
+        && number.chars().all(|c| c.is_ascii_digit())
+        && entry.path().join("meta.json").is_file()
+}
+
+pub struct OutputDir {
+    dir: PathBuf,
+    // (job, entry), in config order once sorted
+    entries: Vec<(usize, serde_json::Value)>,
+}
+
+impl OutputDir {
+    // What an earlier run wrote into `dir` goes, anything else stays.
+    pub fn create(dir: &Path) -> io::Result<OutputDir> {
+        fs::create_dir_all(dir)?;
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if is_task_dir(&entry) {
+                fs::remove_dir_all(entry.path())?;
+            }
+        }
+        match fs::remove_file(dir.join("index.json")) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        Ok(OutputDir {
+            dir: dir.to_path_buf(),
+            entries: Vec::new(),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // the job number keeps names unique if two paths sanitize to the same
+    // string, and the same from one run to the next
+    pub fn write_task(&mut self, result: &TaskResult) -> io::Result<PathBuf> {
+        let name = format!("{:04}-{}", result.job, sanitize(&result.paths[0]));
+        let task_dir = self.dir.join(&name);
+        fs::create_dir_all(&task_dir)?;
+
+        fs::write(task_dir.join("stdout"), &result.stdout)?;
+        fs::write(task_dir.join("stderr"), &result.stderr)?;
+        let meta = result.to_json();
+        fs::write(
+            task_dir.join("meta.json"),
+            serde_json::to_string_pretty(&meta).map_err(io::Error::other)?,
+        )?;
+
+        // a batch shares its logs between all of its paths
+        for path in &result.paths {
+            self.entries.push((
+                result.job,
+                json!({
+                "path": path,
+                "params": meta["params"],
+                "dir": name,
+                "outcome": result.outcome.kind(),
+                "exit": meta["exit"],
+                "duration_ms": meta["duration_ms"],
+                }),
+            ));
+        }
+        Ok(task_dir)
+    }
+
+    pub fn write_index(&self, command: &[String], config: &str) -> io::Result<()> {
+        let mut entries: Vec<&(usize, serde_json::Value)> = self.entries.iter().collect();
+        // stable, so the paths of a batch keep their order
+        entries.sort_by_key(|(job, _)| *job);
+        let tasks: Vec<&serde_json::Value> = entries.into_iter().map(|(_, entry)| entry).collect();
+        let index = json!({
+            "command": command,
+            "config": config,
+            "tasks": tasks,
+        });
+        fs::write(
+            self.dir.join("index.json"),
+            serde_json::to_string_pretty(&index).map_err(io::Error::other)?,
+        )
+    }
+}
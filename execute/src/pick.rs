@@ -0,0 +1,309 @@
+use std::collections::HashSet;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::DefaultTerminal;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use serde_json::{Map, Value};
+
+use crate::config::{self, Entry};
+use crate::log_err;
+use crate::store::{read_json, write_json};
+
+// --pick chooses the paths to run on, --pick-last takes the same ones again
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pick {
+    Interactive,
+    Last,
+}
+
+// The paths picked last per config, kept in `picks.json`:
+//   { "<config path>": ["<path>", ...] }
+struct PickStore {
+    file: PathBuf,
+    picks: Map<String, Value>,
+}
+
+impl PickStore {
+    fn load(data_dir: &Path) -> PickStore {
+        let file = data_dir.join("picks.json");
+        let mut picks = Map::new();
+        if let Some(Value::Object(map)) = read_json(&file) {
+            picks = map;
+        }
+        PickStore { file, picks }
+    }
+
+    fn last(&self, config: &str) -> Option<HashSet<String>> {
+        let paths = self.picks.get(config)?.as_array()?;
+        Some(
+            paths
+                .iter()
+                .filter_map(|path| path.as_str().map(str::to_string))
+                .collect(),
+        )
+    }
+
+    fn save(&mut self, config: &str, paths: &[String]) -> io::Result<()> {
+        self.picks.insert(config.to_string(), Value::from(paths));
+        write_json(&self.file, &Value::Object(self.picks.clone()))
+    }
+}
+
+// `None` unless every character of `query` appears in `text` in order,
+// higher is better: runs of characters and starts of path components
+fn score(text: &str, query: &str) -> Option<i64> {
+    let mut score = 0;
+    let mut previous: Option<char> = None;
+    let mut matched_previous = false;
+    let mut query = query.chars().flat_map(char::to_lowercase).peekable();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        let Some(&wanted) = query.peek() else {
+            break;
+        };
+        if c == wanted {
+            score += 1;
+            if matched_previous {
+                score += 5;
+            }
+            if previous.is_none_or(|previous| matches!(previous, '/' | '-' | '_' | '.')) {
+                score += 3;
+            }
+            query.next();
+            matched_previous = true;
+        } else {
+            matched_previous = false;
+        }
+        previous = Some(c);
+    }
+    if query.peek().is_some() {
+        return None;
+    }
+    Some(score)
+}
+
+struct Picker<'a> {
+    paths: &'a [String],
+    selected: Vec<bool>,
+    query: String,
+    // indices of the paths matching the query, best first
+    visible: Vec<usize>,
+    list_state: ListState,
+}
+
+impl Picker<'_> {
+    fn filter(&mut self) {
+        let mut scored: Vec<(i64, usize)> = self
+            .paths
+            .iter()
+            .enumerate()
+            .filter_map(|(i, path)| score(path, &self.query).map(|score| (score, i)))
+            .collect();
+        // ties keep the config's order
+        scored.sort_by_key(|(score, _)| -score);
+        self.visible = scored.into_iter().map(|(_, i)| i).collect();
+        self.list_state.select(if self.visible.is_empty() {
+            None
+        } else {
+            Some(0)
+        });
+    }
+
+    fn move_by(&mut self, delta: isize) {
+        let Some(last) = self.visible.len().checked_sub(1) else {
+            return;
+        };
+        let position = self.list_state.selected().unwrap_or(0) as isize;
+        let position = position.saturating_add(delta).clamp(0, last as isize);
+        self.list_state.select(Some(position as usize));
+    }
+
+    fn current(&self) -> Option<usize> {
+        self.list_state
+            .selected()
+            .and_then(|position| self.visible.get(position).copied())
+    }
+
+    // every visible path, or none of them if they all are already
+    fn toggle_visible(&mut self) {
+        let all = self.visible.iter().all(|&i| self.selected[i]);
+        for &i in &self.visible {
+            self.selected[i] = !all;
+        }
+    }
+
+    fn draw(&mut self, terminal: &mut DefaultTerminal, config: &str) -> io::Result<()> {
+        let count = self.selected.iter().filter(|&&selected| selected).count();
+        let header = format!(
+            " pick from {} | {} of {} selected",
+            config,
+            count,
+            self.paths.len()
+        );
+        let query = format!(" > {}_", self.query);
+        let footer = " type to filter  Up/Down move  Tab select  Ctrl-A all  Enter run  Esc cancel";
+        let items: Vec<ListItem> = self
+            .visible
+            .iter()
+            .map(|&i| {
+                let mark = if self.selected[i] { "[x]" } else { "[ ]" };
+                ListItem::new(format!("{} {}", mark, self.paths[i]))
+            })
+            .collect();
+        let list_state = &mut self.list_state;
+        terminal.draw(|frame| {
+            let [top, body, prompt, bottom] = Layout::vertical([
+                Constraint::Length(1),
+                Constraint::Min(0),
+                Constraint::Length(1),
+                Constraint::Length(1),
+            ])
+            .areas(frame.area());
+            frame.render_widget(
+                Paragraph::new(header).style(Style::default().add_modifier(Modifier::REVERSED)),
+                top,
+            );
+            frame.render_stateful_widget(
+                List::new(items)
+                    .block(Block::default().borders(Borders::ALL).title(" paths "))
+                    .highlight_style(Style::default().add_modifier(Modifier::REVERSED)),
+                body,
+                list_state,
+            );
+            frame.render_widget(Paragraph::new(query), prompt);
+            frame.render_widget(Paragraph::new(footer), bottom);
+        })?;
+        Ok(())
+    }
+
+    // the picked paths in config order, `None` once cancelled
+    fn run(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        config: &str,
+    ) -> io::Result<Option<Vec<String>>> {
+        self.filter();
+        loop {
+            self.draw(terminal, config)?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let control = key.modifiers.contains(KeyModifiers::CONTROL);
+            match key.code {
+                KeyCode::Esc => return Ok(None),
+                KeyCode::Char('c') if control => return Ok(None),
+                KeyCode::Char('a') if control => self.toggle_visible(),
+                KeyCode::Char('p' | 'k') if control => self.move_by(-1),
+                KeyCode::Char('n' | 'j') if control => self.move_by(1),
+                KeyCode::Up => self.move_by(-1),
+                KeyCode::Down => self.move_by(1),
+                KeyCode::PageUp => self.move_by(-10),
+                KeyCode::PageDown => self.move_by(10),
+                KeyCode::Tab => {
+                    if let Some(i) = self.current() {
+                        self.selected[i] = !self.selected[i];
+                        self.move_by(1);
+                    }
+                }
+                KeyCode::Enter => {
+                    // nothing marked takes the path under the cursor
+                    if !self.selected.contains(&true) {
+                        let Some(i) = self.current() else {
+                            continue;
+                        };
+                        self.selected[i] = true;
+                    }
+                    let picked = self
+                        .paths
+                        .iter()
+                        .zip(&self.selected)
+                        .filter(|(_, selected)| **selected)
+                        .map(|(path, _)| path.clone())
+                        .collect();
+                    return Ok(Some(picked));
+                }
+                KeyCode::Backspace => {
+                    self.query.pop();
+                    self.filter();
+                }
+                KeyCode::Char(c) if !control => {
+                    self.query.push(c);
+                    self.filter();
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// Shows `paths` in a fuzzy finder, `preselected` already marked.
+fn pick(
+    paths: &[String],
+    preselected: &HashSet<String>,
+    config: &str,
+) -> io::Result<Option<Vec<String>>> {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        return Err(io::Error::other("stdin and stdout must be a terminal"));
+    }
+    let mut picker = Picker {
+        paths,
+        selected: paths
+            .iter()
+            .map(|path| preselected.contains(path))
+            .collect(),
+        query: String::new(),
+        visible: Vec::new(),
+        list_state: ListState::default(),
+    };
+    let mut terminal = ratatui::try_init()?;
+    let picked = picker.run(&mut terminal, config);
+    ratatui::restore();
+    picked
+}
+
+// The config's entries the user picks, or picked the last time.
+pub fn entries(
+    pick_mode: Pick,
+    config_path: &PathBuf,
+    data_dir: &Path,
+) -> Result<Vec<Entry>, String> {
+    let entries = config::get_entries(config_path)?;
+    let config = config_path.display().to_string();
+    let mut store = PickStore::load(data_dir);
+    let last = store.last(&config);
+    let picked: HashSet<String> = match pick_mode {
+        Pick::Last => {
+            let Some(last) = last else {
+                return Err(format!("nothing was picked for {} yet, use --pick", config));
+            };
+            last
+        }
+        Pick::Interactive => {
+            let paths: Vec<String> = entries.iter().map(|entry| entry.path.clone()).collect();
+            let picked = pick(&paths, &last.unwrap_or_default(), &config)
+                .map_err(|e| format!("cannot --pick: {}", e))?
+                .ok_or("nothing picked")?;
+            if let Err(e) = store.save(&config, &picked) {
+                log_err!("cannot remember the picked paths: {}", e);
+            }
+            picked.into_iter().collect()
+        }
+    };
+    let entries: Vec<Entry> = entries
+        .into_iter()
+        .filter(|entry| picked.contains(&entry.path))
+        .collect();
+    if entries.is_empty() {
+        return Err(format!(
+            "none of the picked paths are in {} anymore",
+            config
+        ));
+    }
+    Ok(entries)
+}
@@ -0,0 +1,214 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Instant;
+
+use smol::channel::{Receiver, Sender};
+
+use crate::batch::Batcher;
+use crate::config::{Entry, EntryOptions};
+use crate::hooks::{self, Hooks};
+use crate::interrupt;
+use crate::matrix::Params;
+use crate::task::{RunOptions, TaskResult, run_command, working_dir};
+use crate::throttle::{HostLimits, LoadGate, StartGate, remote_host};
+
+// jobs produced ahead of the workers, keeps memory flat for huge path lists
+pub const JOB_BACKLOG: usize = 1024;
+
+// One invocation: a single path, or several with --batch-size.
+#[derive(Clone)]
+pub struct Job {
+    // position in the run, in the order jobs are produced
+    pub id: usize,
+    pub paths: Vec<String>,
+    // the --matrix values it runs with
+    pub params: Params,
+    // what the config line sets for its paths
+    pub options: Arc<EntryOptions>,
+    // git remote host of the first path, only looked up for --per-host-limit
+    pub host: Option<String>,
+    // when it was handed to the workers, for --timings
+    pub queued: Instant,
+}
+
+// What the producer has seen so far, the total is only known at the end.
+#[derive(Default)]
+pub struct Progress {
+    pub paths: AtomicUsize,
+    pub jobs: AtomicUsize,
+    pub complete: AtomicBool,
+}
+
+// What happens to a job before its result arrives, for --tui.
+pub enum Event {
+    Queued(Job),
+    Started(usize),
+}
+
+// Turns paths into jobs on a separate thread, so the first tasks start while
+// the config (or a glob in it) is still being read. Every path (or batch)
+// becomes one job per --matrix combination.
+pub fn produce(
+    entries: impl Iterator<Item = Entry> + Send + 'static,
+    matrix: Vec<Params>,
+    mut batcher: Option<Batcher>,
+    lookup_hosts: bool,
+    progress: Arc<Progress>,
+    events: Option<Sender<Event>>,
+) -> Receiver<Job> {
+    let (sender, receiver) = smol::channel::bounded(JOB_BACKLOG);
+    std::thread::spawn(move || {
+        let send = |paths: Vec<String>, options: &Arc<EntryOptions>| {
+            let mut host = None;
+            if lookup_hosts {
+                host = remote_host(&paths[0]);
+            }
+            for params in &matrix {
+                let job = Job {
+                    id: progress.jobs.fetch_add(1, Ordering::SeqCst),
+                    paths: paths.clone(),
+                    params: params.clone(),
+                    options: options.clone(),
+                    host: host.clone(),
+                    queued: Instant::now(),
+                };
+                if let Some(events) = events.as_ref() {
+                    let _ = events.send_blocking(Event::Queued(job.clone()));
+                }
+                // fails only once nobody takes jobs anymore
+                if sender.send_blocking(job).is_err() {
+                    return false;
+                }
+            }
+            true
+        };
+
+        let mut batch_options = Arc::new(EntryOptions::default());
+        for entry in entries {
+            progress.paths.fetch_add(1, Ordering::SeqCst);
+            let Some(batcher) = batcher.as_mut() else {
+                if !send(vec![entry.path], &entry.options) {
+                    return;
+                }
+                continue;
+            };
+            // a batch runs with one set of options
+            if entry.options != batch_options
+                && let Some(batch) = batcher.take()
+                && !send(batch, &batch_options)
+            {
+                return;
+            }
+            batch_options = entry.options;
+            if let Some(batch) = batcher.push(entry.path)
+                && !send(batch, &batch_options)
+            {
+                return;
+            }
+        }
+        if let Some(batch) = batcher.and_then(|batcher| batcher.finish()) {
+            send(batch, &batch_options);
+        }
+        progress.complete.store(true, Ordering::SeqCst);
+    });
+    receiver
+}
+
+pub struct Pool {
+    pub workers: usize,
+    pub hosts: Option<Arc<HostLimits>>,
+    pub start_gate: Arc<StartGate>,
+    pub load_gate: Option<Arc<LoadGate>>,
+    pub options: Arc<RunOptions>,
+    pub cmd: String,
+    pub cmd_args: Vec<String>,
+    pub events: Option<Sender<Event>>,
+    // --before-each and --after-each
+    pub hooks: Hooks,
+}
+
+impl Pool {
+    async fn next_job(
+        &self,
+        jobs: &Receiver<Job>,
+    ) -> Option<(Job, Option<crate::throttle::HostSlot>)> {
+        match &self.hosts {
+            Some(hosts) => hosts.next_job(jobs).await,
+            None => jobs.recv().await.ok().map(|job| (job, None)),
+        }
+    }
+
+    async fn work(self: Arc<Self>, jobs: Receiver<Job>, results: Sender<Option<TaskResult>>) {
+        loop {
+            let Some((job, _host_slot)) = self.next_job(&jobs).await else {
+                return;
+            };
+            let mut _load_slot = None;
+            if let Some(load_gate) = self.load_gate.as_ref() {
+                _load_slot = Some(load_gate.wait().await);
+            }
+            self.start_gate.wait().await;
+            let waited = job.queued.elapsed();
+
+            // after an interrupt we only count what is left
+            if interrupt::stop_requested() {
+                let _ = results.send(None).await;
+                continue;
+            }
+
+            if let Some(events) = self.events.as_ref() {
+                let _ = events.send(Event::Started(job.id)).await;
+            }
+            let mut result = self.run(job).await;
+            result.waited = waited;
+            let _ = results.send(Some(result)).await;
+        }
+    }
+
+    async fn run(&self, job: Job) -> TaskResult {
+        if let Some(hook) = self.hooks.before_each.as_deref() {
+            let cwd = working_dir(&job.paths, self.options.in_repos, &job.options);
+            if let Err(veto) = hooks::before_each(hook, &cwd, &job.paths, &job.params).await {
+                let mut argv = vec![self.cmd.clone()];
+                argv.extend(self.cmd_args.iter().cloned());
+                let mut result = TaskResult::skipped(job.paths, job.params, argv, cwd, veto.reason);
+                result.stdout = veto.stdout;
+                result.stderr = veto.stderr;
+                result.job = job.id;
+                return result;
+            }
+        }
+
+        let mut result = run_command(
+            self.cmd.clone(),
+            self.cmd_args.clone(),
+            job.paths,
+            job.params,
+            &job.options,
+            &self.options,
+        )
+        .await;
+        result.job = job.id;
+
+        if let Some(hook) = self.hooks.after_each.as_deref() {
+            hooks::after_each(hook, &result).await;
+        }
+        result
+    }
+
+    // `None` results are jobs skipped after an interrupt. The channel closes
+    // once every worker ran out of jobs.
+    pub fn start(self: &Arc<Self>, jobs: Receiver<Job>) -> Receiver<Option<TaskResult>> {
+        let (sender, receiver) = smol::channel::unbounded();
+        for _ in 0..self.workers {
+            smol::spawn(self.clone().work(jobs.clone(), sender.clone())).detach();
+        }
+        receiver
+    }
+
+    // Runs a finished job once more, right away and without the gates.
+    pub fn rerun(self: &Arc<Self>, job: Job) -> smol::Task<TaskResult> {
+        let pool = self.clone();
+        smol::spawn(async move { pool.run(job).await })
+    }
+}
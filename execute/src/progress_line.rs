@@ -0,0 +1,194 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use futures_lite::FutureExt;
+use smol::channel::Receiver;
+
+use crate::matrix::{self, Params};
+use crate::pool::{Event, Progress};
+use crate::pty;
+use crate::task::TaskResult;
+use crate::timings::format_duration;
+
+// how often elapsed times and the ETA are redrawn
+const TICK: Duration = Duration::from_millis(100);
+// running tasks listed below the summary, the rest is counted
+const RUNNING_SHOWN: usize = 5;
+
+// A few lines at the bottom of stderr, redrawn in place:
+//
+//   [12/40] 3 running, 1 failed, ETA 42s
+//     /home/me/Repos/a 3.2s
+//     ...
+//
+// Whoever prints in between calls `clear` first, the next tick redraws.
+pub struct ProgressLine {
+    progress: Arc<Progress>,
+    events: Receiver<Event>,
+    events_closed: bool,
+    // paths of jobs that were produced but did not start yet
+    queued: HashMap<usize, (Vec<String>, Params)>,
+    running: BTreeMap<usize, (String, Instant)>,
+    done: usize,
+    failed: usize,
+    first_start: Option<Instant>,
+    // lines currently on screen
+    drawn: usize,
+    next_draw: Instant,
+}
+
+impl ProgressLine {
+    pub fn new(progress: Arc<Progress>, events: Receiver<Event>) -> ProgressLine {
+        ProgressLine {
+            progress,
+            events,
+            events_closed: false,
+            queued: HashMap::new(),
+            running: BTreeMap::new(),
+            done: 0,
+            failed: 0,
+            first_start: None,
+            drawn: 0,
+            next_draw: Instant::now() + TICK,
+        }
+    }
+
+    // Waits for a job event or the next redraw.
+    pub async fn next(&mut self) {
+        let events = &self.events;
+        let events_closed = self.events_closed;
+        let next_draw = self.next_draw;
+
+        let event = async {
+            if events_closed {
+                std::future::pending().await
+            }
+            Some(events.recv().await)
+        };
+        let tick = async {
+            smol::Timer::at(next_draw).await;
+            None
+        };
+        match event.or(tick).await {
+            Some(Ok(event)) => self.apply(event),
+            Some(Err(_)) => self.events_closed = true,
+            None => self.draw(),
+        }
+    }
+
+    fn apply(&mut self, event: Event) {
+        match event {
+            Event::Queued(job) => {
+                self.queued.insert(job.id, (job.paths, job.params));
+            }
+            Event::Started(id) => {
+                let label = match self.queued.remove(&id) {
+                    Some((paths, params)) if paths.len() > 1 => format!(
+                        "{} (+{} more){}",
+                        paths[0],
+                        paths.len() - 1,
+                        matrix::label(&params)
+                    ),
+                    Some((paths, params)) => format!("{}{}", paths[0], matrix::label(&params)),
+                    None => String::new(),
+                };
+                let now = Instant::now();
+                self.first_start.get_or_insert(now);
+                self.running.insert(id, (label, now));
+            }
+        }
+    }
+
+    pub fn finished(&mut self, result: &TaskResult) {
+        // a result can overtake the events of its job
+        while let Ok(event) = self.events.try_recv() {
+            self.apply(event);
+        }
+        self.running.remove(&result.job);
+        self.done += 1;
+        if !result.outcome.is_success() {
+            self.failed += 1;
+        }
+    }
+
+    fn eta(&self) -> String {
+        if !self.progress.complete.load(Ordering::SeqCst) || self.done == 0 {
+            return "?".to_string();
+        }
+        let Some(first_start) = self.first_start else {
+            return "?".to_string();
+        };
+        let total = self.progress.jobs.load(Ordering::SeqCst);
+        let per_task = first_start.elapsed().as_secs_f64() / self.done as f64;
+        let remaining = per_task * total.saturating_sub(self.done) as f64;
+        format_duration(Duration::from_secs_f64(remaining))
+    }
+
+    fn draw(&mut self) {
+        self.next_draw = Instant::now() + TICK;
+
+        let total = self.progress.jobs.load(Ordering::SeqCst);
+        let complete = self.progress.complete.load(Ordering::SeqCst);
+        let mut lines = vec![format!(
+            "[{}/{}{}] {} running, {} failed, ETA {}",
+            self.done,
+            total,
+            if complete { "" } else { "+" },
+            self.running.len(),
+            self.failed,
+            self.eta()
+        )];
+        let mut running: Vec<&(String, Instant)> = self.running.values().collect();
+        running.sort_by_key(|(_, started)| *started);
+        for (label, started) in running.iter().take(RUNNING_SHOWN) {
+            lines.push(format!(
+                "  {} {}",
+                label,
+                format_duration(started.elapsed())
+            ));
+        }
+        if running.len() > RUNNING_SHOWN {
+            lines.push(format!("  ... {} more", running.len() - RUNNING_SHOWN));
+        }
+
+        // a wrapped line would throw off moving back up
+        let (cols, _) = pty::terminal_size();
+        let width = (cols as usize).saturating_sub(1);
+        let mut out = self.erase_sequence();
+        for line in &lines {
+            out.push_str(&line.chars().take(width).collect::<String>());
+            out.push('\n');
+        }
+        let mut stderr = io::stderr().lock();
+        let _ = stderr.write_all(out.as_bytes());
+        let _ = stderr.flush();
+        self.drawn = lines.len();
+    }
+
+    fn erase_sequence(&self) -> String {
+        if self.drawn == 0 {
+            return String::new();
+        }
+        // up to the first line we drew, then clear to the end of the screen
+        format!("\x1b[{}A\r\x1b[J", self.drawn)
+    }
+
+    pub fn clear(&mut self) {
+        if self.drawn == 0 {
+            return;
+        }
+        let mut stderr = io::stderr().lock();
+        let _ = stderr.write_all(self.erase_sequence().as_bytes());
+        let _ = stderr.flush();
+        self.drawn = 0;
+    }
+}
+
+impl Drop for ProgressLine {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
@@ -0,0 +1,119 @@
+use std::ffi::{CStr, c_char};
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+// columns x rows
+pub type PtySize = (u16, u16);
+
+const DEFAULT_SIZE: PtySize = (80, 24);
+
+// size of the terminal we run in, so tasks format their output for it
+pub fn terminal_size() -> PtySize {
+    // SAFETY: winsize is plain integers, all zeroes is a valid value
+    let mut winsize: libc::winsize = unsafe { std::mem::zeroed() };
+    for fd in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        // SAFETY: TIOCGWINSZ only writes into the winsize we pass
+        let ret = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut winsize) };
+        if ret == 0 && winsize.ws_col > 0 && winsize.ws_row > 0 {
+            return (winsize.ws_col, winsize.ws_row);
+        }
+    }
+    DEFAULT_SIZE
+}
+
+// `120x40`
+pub fn parse_size(value: &str) -> Result<PtySize, String> {
+    let invalid = || format!("invalid pty size {:?}, expected <columns>x<rows>", value);
+    let (cols, rows) = value.split_once('x').ok_or_else(invalid)?;
+    let cols: u16 = cols.parse().map_err(|_| invalid())?;
+    let rows: u16 = rows.parse().map_err(|_| invalid())?;
+    if cols == 0 || rows == 0 {
+        return Err(invalid());
+    }
+    Ok((cols, rows))
+}
+
+fn check(ret: libc::c_int) -> io::Result<()> {
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// /dev/pts/N of `master`, ptsname(3) is not thread-safe
+fn slave_name(master: &OwnedFd, name: &mut [c_char; 128]) -> io::Result<()> {
+    // SAFETY: both write at most `name.len()` bytes, NUL included
+    #[cfg(target_vendor = "apple")]
+    check(unsafe {
+        libc::ioctl(
+            master.as_raw_fd(),
+            libc::TIOCPTYGNAME as _,
+            name.as_mut_ptr(),
+        )
+    })?;
+    #[cfg(not(target_vendor = "apple"))]
+    check(unsafe { libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()) })?;
+    Ok(())
+}
+
+// Opens a pseudo-terminal pair. The master is non-blocking so it can be
+// read with `smol::Async`, the slave does no output processing (no `\r\n`).
+// Both are close-on-exec from the start, a task spawned meanwhile holding on
+// to our slave would keep the master from seeing EOF.
+pub fn open(size: PtySize) -> io::Result<(smol::Async<File>, OwnedFd)> {
+    // macOS takes nothing but these two, a master it leaks holds nothing back
+    #[cfg(target_vendor = "apple")]
+    let flags = libc::O_RDWR | libc::O_NOCTTY;
+    #[cfg(not(target_vendor = "apple"))]
+    let flags = libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC;
+    // SAFETY: plain syscall, the fd is owned from here on
+    let master = unsafe { libc::posix_openpt(flags) };
+    if master < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `master` is a fresh descriptor nobody else owns
+    let master = unsafe { OwnedFd::from_raw_fd(master) };
+    let mut name = [0; 128];
+    // SAFETY: plain calls on the fd we own
+    unsafe {
+        #[cfg(target_vendor = "apple")]
+        libc::fcntl(master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC);
+        check(libc::grantpt(master.as_raw_fd()))?;
+        check(libc::unlockpt(master.as_raw_fd()))?;
+    }
+    slave_name(&master, &mut name)?;
+    // SAFETY: `slave_name` NUL-terminated `name`
+    let slave = unsafe {
+        libc::open(
+            CStr::from_ptr(name.as_ptr()).as_ptr(),
+            libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC,
+        )
+    };
+    if slave < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `slave` is a fresh descriptor nobody else owns
+    let slave = unsafe { OwnedFd::from_raw_fd(slave) };
+
+    let winsize = libc::winsize {
+        ws_col: size.0,
+        ws_row: size.1,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: plain ioctl/termios calls on fds we own, termios is plain
+    // integers and all zeroes is a valid value
+    unsafe {
+        check(libc::ioctl(slave.as_raw_fd(), libc::TIOCSWINSZ, &winsize))?;
+
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(slave.as_raw_fd(), &mut termios) == 0 {
+            termios.c_oflag &= !libc::OPOST;
+            libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios);
+        }
+    }
+
+    let master = smol::Async::new(File::from(master))?;
+    Ok((master, slave))
+}
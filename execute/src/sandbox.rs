@@ -0,0 +1,301 @@
+#[cfg(target_os = "linux")]
+use std::ffi::CStr;
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::fd::FromRawFd;
+use std::os::fd::{AsRawFd, OwnedFd};
+
+// not in the libc crate yet
+#[cfg(target_os = "linux")]
+const MOUNT_ATTR_RDONLY: u64 = 0x1;
+#[cfg(target_os = "linux")]
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x20080522;
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
+// --read-only and --no-network: every task in its own user namespace with
+// a read-only view of the filesystem and/or without network, and without
+// capabilities.
+#[derive(Clone, Copy, Default)]
+pub struct Sandbox {
+    pub read_only: bool,
+    // an empty, writable tmpfs on /tmp, with --read-only
+    pub writable_tmp: bool,
+    pub no_network: bool,
+}
+
+// What the child needs, prepared before fork.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub struct Setup {
+    sandbox: Sandbox,
+    uid_map: Vec<u8>,
+    gid_map: Vec<u8>,
+    // the child writes the step that failed, closed on exec
+    report: OwnedFd,
+}
+
+// Tells which step of the setup failed once spawning did.
+pub struct Report {
+    failed_step: OwnedFd,
+}
+
+impl Sandbox {
+    pub fn is_empty(&self) -> bool {
+        !self.read_only && !self.no_network
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn prepare(&self) -> io::Result<(Setup, Report)> {
+        let mut fds = [0; 2];
+        // SAFETY: `fds` has room for both ends
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: both were just opened and are owned by nobody else
+        let (read, write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        // SAFETY: cannot fail
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        Ok((
+            Setup {
+                sandbox: *self,
+                uid_map: format!("{} {} 1\n", uid, uid).into_bytes(),
+                gid_map: format!("{} {} 1\n", gid, gid).into_bytes(),
+                report: write,
+            },
+            Report { failed_step: read },
+        ))
+    }
+
+    // the flags are refused before it gets here
+    #[cfg(not(target_os = "linux"))]
+    pub fn prepare(&self) -> io::Result<(Setup, Report)> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn check(ret: libc::c_int) -> io::Result<()> {
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn write_file(path: &CStr, content: &[u8]) -> io::Result<()> {
+    // SAFETY: `path` is NUL-terminated, `content` outlives the calls
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let written = libc::write(fd, content.as_ptr().cast(), content.len());
+        let error = io::Error::last_os_error();
+        libc::close(fd);
+        if written != content.len() as isize {
+            return Err(error);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+impl Setup {
+    // a new user namespace (plus `flags`) where we keep our ids
+    fn unshare(&self, flags: libc::c_int) -> io::Result<()> {
+        // SAFETY: plain syscall on our own process
+        check(unsafe { libc::unshare(libc::CLONE_NEWUSER | flags) })?;
+        // required before an unprivileged gid_map
+        write_file(c"/proc/self/setgroups", b"deny")?;
+        write_file(c"/proc/self/uid_map", &self.uid_map)?;
+        write_file(c"/proc/self/gid_map", &self.gid_map)
+    }
+
+    fn read_only(&self) -> io::Result<()> {
+        // SAFETY: plain syscalls, the strings are NUL-terminated
+        unsafe {
+            // nothing of ours reaches the real mounts
+            check(libc::mount(
+                std::ptr::null(),
+                c"/".as_ptr(),
+                std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            ))?;
+            let attr = MountAttr {
+                attr_set: MOUNT_ATTR_RDONLY,
+                attr_clr: 0,
+                propagation: 0,
+                userns_fd: 0,
+            };
+            let ret = libc::syscall(
+                libc::SYS_mount_setattr,
+                libc::AT_FDCWD,
+                c"/".as_ptr(),
+                libc::AT_RECURSIVE,
+                &attr as *const MountAttr,
+                size_of::<MountAttr>(),
+            );
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    fn writable_tmp(&self) -> io::Result<()> {
+        // SAFETY: plain syscall, the strings are NUL-terminated
+        check(unsafe {
+            libc::mount(
+                c"tmpfs".as_ptr(),
+                c"/tmp".as_ptr(),
+                c"tmpfs".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV,
+                c"mode=1777".as_ptr().cast(),
+            )
+        })
+    }
+
+    // a new network namespace only has `lo`, and that is down
+    fn loopback_up(&self) -> io::Result<()> {
+        // SAFETY: plain syscalls, `request` outlives the ioctl
+        unsafe {
+            let socket = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+            if socket < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut request: libc::ifreq = std::mem::zeroed();
+            request.ifr_name[0] = b'l' as libc::c_char;
+            request.ifr_name[1] = b'o' as libc::c_char;
+            request.ifr_ifru.ifru_flags = (libc::IFF_UP | libc::IFF_RUNNING) as libc::c_short;
+            // the request is a `c_int` on musl
+            let ret = libc::ioctl(socket, libc::SIOCSIFFLAGS as _, &request);
+            let error = io::Error::last_os_error();
+            libc::close(socket);
+            if ret != 0 {
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    // The task would be all-powerful in its namespace and could remount
+    // read-write. Without capabilities and a bounding set not even root
+    // gets them back on exec.
+    fn drop_capabilities(&self) -> io::Result<()> {
+        // SAFETY: plain syscalls on our own process, the structs outlive them
+        unsafe {
+            check(libc::prctl(
+                libc::PR_CAP_AMBIENT,
+                libc::PR_CAP_AMBIENT_CLEAR_ALL,
+                0,
+                0,
+                0,
+            ))?;
+            for capability in 0..64 {
+                if libc::prctl(libc::PR_CAPBSET_DROP, capability, 0, 0, 0) != 0 {
+                    let error = io::Error::last_os_error();
+                    // past the last capability the kernel knows
+                    if error.raw_os_error() == Some(libc::EINVAL) {
+                        break;
+                    }
+                    return Err(error);
+                }
+            }
+            let header = CapHeader {
+                version: LINUX_CAPABILITY_VERSION_3,
+                pid: 0,
+            };
+            let data = [CapData::default(); 2];
+            let ret = libc::syscall(libc::SYS_capset, &header as *const CapHeader, data.as_ptr());
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    fn failed(&self, step: &str, error: io::Error) -> io::Error {
+        // SAFETY: `step` outlives the call, the pipe cannot fill up with one step
+        unsafe {
+            libc::write(self.report.as_raw_fd(), step.as_ptr().cast(), step.len());
+        }
+        error
+    }
+
+    // Runs after fork, only async-signal-safe calls in here.
+    pub fn apply(&self) -> io::Result<()> {
+        let mut flags = 0;
+        if self.sandbox.read_only {
+            flags |= libc::CLONE_NEWNS;
+        }
+        if self.sandbox.no_network {
+            flags |= libc::CLONE_NEWNET;
+        }
+        self.unshare(flags)
+            .map_err(|e| self.failed("user namespace", e))?;
+        if self.sandbox.no_network {
+            self.loopback_up().map_err(|e| self.failed("loopback", e))?;
+        }
+        if self.sandbox.read_only {
+            self.read_only()
+                .map_err(|e| self.failed("read-only mounts", e))?;
+            if self.sandbox.writable_tmp {
+                self.writable_tmp()
+                    .map_err(|e| self.failed("tmpfs on /tmp", e))?;
+            }
+        }
+        self.drop_capabilities()
+            .map_err(|e| self.failed("capabilities", e))?;
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Setup {
+    pub fn apply(&self) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+impl Report {
+    // the step that failed, if the setup got that far
+    pub fn failed_step(&self) -> Option<String> {
+        let mut buffer = [0u8; 64];
+        // SAFETY: `buffer` has room for what is read
+        let read = unsafe {
+            libc::read(
+                self.failed_step.as_raw_fd(),
+                buffer.as_mut_ptr().cast(),
+                buffer.len(),
+            )
+        };
+        if read <= 0 {
+            return None;
+        }
+        Some(String::from_utf8_lossy(&buffer[..read as usize]).to_string())
+    }
+}
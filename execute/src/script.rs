@@ -0,0 +1,54 @@
+use std::fs;
+
+// `--sh 'make lint && make test'` or `--sh-file ci/check.sh`, run instead
+// of a command with the task's paths as $1... and $EXECUTE_PATH
+pub enum Script {
+    Inline(String),
+    File {
+        // as given
+        name: String,
+        // absolute, the script runs in every repo
+        path: String,
+    },
+}
+
+// what $0 of an inline script is, e.g. in the shell's error messages
+const INLINE_NAME: &str = "execute";
+
+pub fn file(value: &str) -> Result<Script, String> {
+    let path =
+        fs::canonicalize(value).map_err(|e| format!("cannot read --sh-file {}: {}", value, e))?;
+    Ok(Script::File {
+        name: value.to_string(),
+        path: path.display().to_string(),
+    })
+}
+
+// --shell, else $SHELL, else `sh`
+pub fn shell(chosen: Option<String>) -> String {
+    chosen
+        .or_else(|| std::env::var("SHELL").ok())
+        .filter(|shell| !shell.is_empty())
+        .unwrap_or_else(|| "sh".to_string())
+}
+
+// `bash -c '<script>' execute` or `bash /abs/check.sh`, the paths follow
+pub fn command(script: &Script, shell: String) -> Vec<String> {
+    match script {
+        Script::Inline(script) => vec![
+            shell,
+            "-c".to_string(),
+            script.clone(),
+            INLINE_NAME.to_string(),
+        ],
+        Script::File { path, .. } => vec![shell, path.clone()],
+    }
+}
+
+// how the run shows up in the TUI, the history and the reports
+pub fn title(script: &Script) -> Vec<String> {
+    match script {
+        Script::Inline(script) => vec![script.clone()],
+        Script::File { name, .. } => vec![name.clone()],
+    }
+}
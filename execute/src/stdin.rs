@@ -0,0 +1,75 @@
+use std::ffi::{CString, OsStr};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::fd::FromRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileExt;
+
+// larger input goes to a temporary file instead of staying in memory
+const MAX_BUFFERED: usize = 16 * 1024 * 1024;
+
+// Our own stdin, read once and handed to every task (--stdin-broadcast).
+pub enum StdinSource {
+    Buffer(Vec<u8>),
+    // already unlinked, every task is fed from it at its own offset
+    Spooled(File),
+}
+
+// a fresh file in the temp dir with a name nobody can guess ahead of us
+fn create_spool() -> io::Result<File> {
+    let template = std::env::temp_dir().join("execute-stdin-XXXXXX");
+    let mut template = CString::new(template.as_os_str().as_bytes())
+        .map_err(io::Error::other)?
+        .into_bytes_with_nul();
+    // SAFETY: `template` is NUL-terminated and mkstemp only rewrites the Xs
+    let fd = unsafe { libc::mkstemp(template.as_mut_ptr().cast()) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `fd` is a fresh descriptor nobody else owns
+    let file = unsafe { File::from_raw_fd(fd) };
+    template.pop();
+    fs::remove_file(OsStr::from_bytes(&template))?;
+    Ok(file)
+}
+
+impl StdinSource {
+    pub fn read() -> io::Result<StdinSource> {
+        let mut stdin = io::stdin().lock();
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 64 * 1024];
+        loop {
+            let n = stdin.read(&mut chunk)?;
+            if n == 0 {
+                return Ok(StdinSource::Buffer(buf));
+            }
+            buf.extend_from_slice(&chunk[..n]);
+            if buf.len() > MAX_BUFFERED {
+                break;
+            }
+        }
+
+        let mut file = create_spool()?;
+        file.write_all(&buf)?;
+        io::copy(&mut stdin, &mut file)?;
+        Ok(StdinSource::Spooled(file))
+    }
+
+    // the next piece of spooled input from `offset` on, empty at the end
+    pub fn chunk_at(&self, offset: u64) -> io::Result<Vec<u8>> {
+        let StdinSource::Spooled(file) = self else {
+            return Ok(Vec::new());
+        };
+        let mut chunk = vec![0u8; 64 * 1024];
+        let n = file.read_at(&mut chunk, offset)?;
+        chunk.truncate(n);
+        Ok(chunk)
+    }
+
+    pub fn len(&self) -> u64 {
+        match self {
+            StdinSource::Buffer(buf) => buf.len() as u64,
+            StdinSource::Spooled(file) => file.metadata().map(|meta| meta.len()).unwrap_or(0),
+        }
+    }
+}
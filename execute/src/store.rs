@@ -0,0 +1,31 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// `$XDG_DATA_HOME/execute` or `~/.local/share/execute`
+pub fn data_dir(home: &str) -> PathBuf {
+    if let Ok(xdg) = std::env::var("XDG_DATA_HOME")
+        && !xdg.is_empty()
+    {
+        return PathBuf::from(xdg).join("execute");
+    }
+    PathBuf::from(home).join(".local/share/execute")
+}
+
+pub fn read_json(file: &Path) -> Option<serde_json::Value> {
+    let content = fs::read_to_string(file).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+// write to a temporary file first so concurrent runs never see half a file
+pub fn write_json(file: &Path, value: &serde_json::Value) -> io::Result<()> {
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = file.with_extension(format!("tmp.{}", std::process::id()));
+    fs::write(
+        &tmp,
+        serde_json::to_string(value).map_err(io::Error::other)?,
+    )?;
+    fs::rename(&tmp, file)
+}
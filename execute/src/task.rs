@@ -0,0 +1,523 @@
+use async_process::{Command, Stdio};
+
+use futures_lite::future::zip;
+use futures_lite::{AsyncReadExt, AsyncWriteExt};
+use smol_timeout::TimeoutExt;
+
+use std::io::IsTerminal;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use serde_json::json;
+
+use crate::config::EntryOptions;
+use crate::debug;
+use crate::env_file::{self, LoadEnv};
+use crate::hooks;
+use crate::interrupt;
+use crate::limits::{self, CpuUsage, Limits};
+use crate::matrix::{self, Params};
+use crate::pty::{self, PtySize};
+use crate::sandbox::{Report, Sandbox};
+use crate::stdin::StdinSource;
+
+// how long we keep reading a pipe after the child is gone;
+// background grandchildren may hold on to it forever
+const DRAIN_GRACE: Duration = Duration::from_millis(100);
+
+// directories and files `grep` should never look at
+pub const GREP_EXCLUDES: [&str; 19] = [
+    "--exclude-dir=.git",
+    "--exclude-dir=.helm",
+    "--exclude-dir=.tox",
+    "--exclude-dir=.pulumi",
+    "--exclude-dir=.cache",
+    "--exclude-dir=.mypy_cache",
+    "--exclude-dir=.eggs",
+    "--exclude-dir=*.egg-info",
+    "--exclude-dir=*venv*",
+    "--exclude-dir=_build",
+    "--exclude-dir=__pycache__",
+    "--exclude-dir=.ruff_cache",
+    "--exclude=\"*.pyc\"",
+    "--exclude-dir=.pytest_cache",
+    "--exclude=poetry.lock",
+    "--exclude-dir=htmlcov",
+    "--exclude=\"*.html\"",
+    "--exclude=build.*trace",
+    "--exclude=Session.vim",
+];
+
+#[derive(Clone)]
+pub struct RunOptions {
+    pub use_color: bool,
+    pub in_repos: bool, // whether to operate on files or in repos
+    pub timeout: Option<Duration>,
+    // run the task with its stdout and stderr connected to pseudo-terminals
+    pub pty_size: Option<PtySize>,
+    // fed to every task instead of inheriting our stdin
+    pub stdin: Option<Arc<StdinSource>>,
+    // niceness and resource limits of every task
+    pub limits: Limits,
+    // a repo's env file, applied before the config's env
+    pub load_env: Option<LoadEnv>,
+    // --sh: the paths are the script's arguments, also in repos
+    pub is_script: bool,
+    // --read-only and --no-network
+    pub sandbox: Sandbox,
+    // tasks stay in our process group, the terminal's foreground
+    pub foreground: bool,
+}
+
+pub enum Outcome {
+    Exited(i32),
+    Signaled(i32),
+    // killed by the kernel for going over a --rlimit-*, signal and reason
+    LimitExceeded(i32, &'static str),
+    TimedOut(Duration),
+    SpawnFailed(String),
+    WaitFailed(String),
+    // --read-only or --no-network could not be set up, the task never ran
+    SandboxFailed(String),
+    // never started, why: a --deps dependency failed, --before-each vetoed
+    Skipped(String),
+}
+
+impl Outcome {
+    pub fn is_success(&self) -> bool {
+        matches!(self, Outcome::Exited(0))
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Outcome::Exited(0) => "ok",
+            Outcome::Exited(_) => "non-zero",
+            Outcome::Signaled(_) => "signal",
+            Outcome::LimitExceeded(_, _) => "limit",
+            Outcome::TimedOut(_) => "timeout",
+            Outcome::SpawnFailed(_) => "spawn-failed",
+            Outcome::WaitFailed(_) => "wait-failed",
+            Outcome::SandboxFailed(_) => "sandbox-failed",
+            Outcome::Skipped(_) => "skipped",
+        }
+    }
+}
+
+pub struct TaskResult {
+    // the path, or the first path of a batch
+    pub path: String,
+    // every path the result belongs to (several with --batch-size)
+    pub paths: Vec<String>,
+    // --matrix values, in the environment and `{NAME}` of the command
+    pub params: Params,
+    pub argv: Vec<String>,
+    pub cwd: PathBuf,
+    pub outcome: Outcome,
+    pub stdout: String,
+    pub stderr: String,
+    pub started: DateTime<Local>,
+    pub finished: DateTime<Local>,
+    pub duration: Duration,
+    // time spent waiting for a free slot before the spawn
+    pub waited: Duration,
+    // id of the job this is the result of, set by the pool
+    pub job: usize,
+}
+
+impl TaskResult {
+    pub fn skipped(
+        paths: Vec<String>,
+        params: Params,
+        argv: Vec<String>,
+        cwd: PathBuf,
+        reason: String,
+    ) -> TaskResult {
+        let now = Local::now();
+        TaskResult {
+            path: paths[0].clone(),
+            paths,
+            params,
+            argv,
+            cwd,
+            outcome: Outcome::Skipped(reason),
+            stdout: String::new(),
+            stderr: String::new(),
+            started: now,
+            finished: now,
+            duration: Duration::ZERO,
+            waited: Duration::ZERO,
+            job: 0,
+        }
+    }
+
+    // `'<path>'`, batches also tell how many paths they hold, matrix
+    // tasks their values
+    pub fn label(&self) -> String {
+        let params = matrix::label(&self.params);
+        if self.paths.len() > 1 {
+            return format!("'{}' (+{} more){}", self.path, self.paths.len() - 1, params);
+        }
+        format!("'{}'{}", self.path, params)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut exit = serde_json::Value::Null;
+        let mut signal = serde_json::Value::Null;
+        let mut error = serde_json::Value::Null;
+        match &self.outcome {
+            Outcome::Exited(code) => exit = json!(code),
+            Outcome::Signaled(sig) => signal = json!(sig),
+            Outcome::LimitExceeded(sig, reason) => {
+                signal = json!(sig);
+                error = json!(reason);
+            }
+            Outcome::SpawnFailed(err) | Outcome::WaitFailed(err) | Outcome::SandboxFailed(err) => {
+                error = json!(err)
+            }
+            Outcome::Skipped(reason) => error = json!(reason),
+            Outcome::TimedOut(_) => {}
+        }
+
+        let params: serde_json::Map<String, serde_json::Value> = self
+            .params
+            .iter()
+            .map(|(name, value)| (name.clone(), json!(value)))
+            .collect();
+
+        json!({
+            "path": self.path,
+            "paths": self.paths,
+            "params": params,
+            "argv": self.argv,
+            "cwd": self.cwd.to_string_lossy(),
+            "outcome": self.outcome.kind(),
+            "exit": exit,
+            "signal": signal,
+            "error": error,
+            "started": self.started.to_rfc3339(),
+            "finished": self.finished.to_rfc3339(),
+            "duration_ms": self.duration.as_millis() as u64,
+            "waited_ms": self.waited.as_millis() as u64,
+        })
+    }
+}
+
+// Reads a pipe in the background so that whatever the child wrote
+// is still available if we have to give up on it (timeout).
+struct Capture {
+    buf: Arc<Mutex<Vec<u8>>>,
+    task: smol::Task<()>,
+}
+
+impl Capture {
+    fn start<R: futures_lite::AsyncRead + Unpin + Send + 'static>(mut pipe: R) -> Capture {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let buf_clone = buf.clone();
+        let task = smol::spawn(async move {
+            let mut chunk = [0u8; 8192];
+            loop {
+                match pipe.read(&mut chunk).await {
+                    Ok(0) => break,
+                    Ok(n) => buf_clone.lock().unwrap().extend_from_slice(&chunk[..n]),
+                    // a pty master reports EIO once the task is gone
+                    Err(_err) => {
+                        debug!("Error reading pipe: {:?}", _err);
+                        break;
+                    }
+                }
+            }
+        });
+        Capture { buf, task }
+    }
+
+    async fn finish(self) -> String {
+        // dropping the task (after the grace period) stops reading
+        let _ = self.task.timeout(DRAIN_GRACE).await;
+        let buf = self.buf.lock().unwrap();
+        let text = String::from_utf8_lossy(&buf);
+        text.strip_suffix('\n').unwrap_or(&text).to_string()
+    }
+}
+
+pub fn build_args(cmd: &str, arguments: &[String], use_color: bool) -> Vec<String> {
+    let mut args: Vec<String> = Vec::new();
+
+    if use_color {
+        if cmd == "git" {
+            args.push("-c".to_string());
+            args.push("color.status=always".to_string());
+        } else if cmd == "grep" {
+            args.push("--color=always".to_string());
+        }
+    }
+    if cmd == "grep" {
+        for exclude in GREP_EXCLUDES {
+            args.push(exclude.to_string());
+        }
+    }
+    args.extend(arguments.iter().cloned());
+    args
+}
+
+// a repo (or its `cwd=`), with --files our own directory (or `cwd=`)
+pub fn working_dir(files: &[String], in_repos: bool, entry: &EntryOptions) -> PathBuf {
+    let mut dir = if in_repos {
+        PathBuf::from(&files[0])
+    } else {
+        std::env::current_dir().unwrap_or_default()
+    };
+    if let Some(cwd) = entry.cwd.as_deref() {
+        dir = dir.join(cwd);
+    }
+    dir
+}
+
+pub async fn run_command(
+    cmd: String,
+    arguments: Vec<String>,
+    files: Vec<String>,
+    params: Params,
+    entry: &EntryOptions,
+    options: &RunOptions,
+) -> TaskResult {
+    let cmd = matrix::substitute(&cmd, &params);
+    let arguments: Vec<String> = arguments
+        .iter()
+        .map(|arg| matrix::substitute(arg, &params))
+        .collect();
+    // under a pty tools pick color on their own
+    let use_color = options.use_color && options.pty_size.is_none();
+    let mut args = build_args(&cmd, &arguments, use_color);
+
+    let mut std_command = std::process::Command::new(cmd.clone());
+    // own process group, so signals can be forwarded to everything the task
+    // starts, unless it shares the terminal with us
+    if !options.foreground {
+        std_command.process_group(0);
+    }
+    let mut sandbox_report: Option<Report> = None;
+    let mut sandbox_error: Option<std::io::Error> = None;
+    if !options.sandbox.is_empty() {
+        match options.sandbox.prepare() {
+            Ok((setup, report)) => {
+                // SAFETY: `apply` only makes async-signal-safe syscalls
+                unsafe {
+                    std_command.pre_exec(move || setup.apply());
+                }
+                sandbox_report = Some(report);
+            }
+            Err(e) => sandbox_error = Some(e),
+        }
+    }
+    if !options.limits.is_empty() {
+        let limits = options.limits;
+        // SAFETY: `apply` only makes async-signal-safe syscalls
+        unsafe {
+            std_command.pre_exec(move || limits.apply());
+        }
+    }
+    let mut command = Command::from(std_command);
+    let cwd = working_dir(&files, options.in_repos, entry);
+    if options.in_repos || entry.cwd.is_some() {
+        command.current_dir(&cwd);
+    }
+    if !options.in_repos || options.is_script {
+        args.extend(files.iter().cloned());
+    }
+    let timeout = entry.timeout.or(options.timeout);
+
+    let mut argv = vec![cmd.clone()];
+    argv.extend(args.iter().cloned());
+
+    let path = files[0].clone();
+    let started = Local::now();
+    let start = Instant::now();
+    let result = |outcome: Outcome, stdout: String, stderr: String| TaskResult {
+        path: path.clone(),
+        paths: files.clone(),
+        params: params.clone(),
+        argv: argv.clone(),
+        cwd: cwd.clone(),
+        outcome,
+        stdout,
+        stderr,
+        started,
+        finished: Local::now(),
+        duration: start.elapsed(),
+        waited: Duration::ZERO,
+        job: 0,
+    };
+
+    if let Some(e) = sandbox_error {
+        let outcome =
+            Outcome::SandboxFailed(format!("Cannot set up the sandbox for '{}': {}", path, e));
+        return result(outcome, String::new(), String::new());
+    }
+    if let Some(load_env) = options.load_env {
+        match env_file::load(Path::new(&path), load_env).await {
+            Ok(env) => {
+                command.envs(env);
+            }
+            Err(e) => {
+                let outcome =
+                    Outcome::SpawnFailed(format!("Cannot load env for '{}': {}", path, e));
+                return result(outcome, String::new(), String::new());
+            }
+        }
+    }
+    if options.is_script {
+        command.envs(hooks::task_env(&files, &params));
+    }
+    command.envs(entry.env.iter().map(|(name, value)| (name, value)));
+    command.envs(params.iter().map(|(name, value)| (name, value)));
+
+    // one terminal each for stdout and stderr, so they stay apart
+    let mut pty_masters = None;
+    if let Some(size) = options.pty_size {
+        match zip_ptys(size) {
+            Ok(((stdout_master, stdout_slave), (stderr_master, stderr_slave))) => {
+                command.stdout(Stdio::from(stdout_slave));
+                command.stderr(Stdio::from(stderr_slave));
+                pty_masters = Some((stdout_master, stderr_master));
+            }
+            Err(e) => {
+                let outcome =
+                    Outcome::SpawnFailed(format!("Cannot open pty for '{}': {}", path, e));
+                return result(outcome, String::new(), String::new());
+            }
+        }
+    } else {
+        command.stdout(Stdio::piped());
+        command.stderr(Stdio::piped());
+    }
+
+    match options.stdin.as_deref() {
+        Some(_) => {
+            command.stdin(Stdio::piped());
+        }
+        // in a background group reading the terminal would stop the task
+        None if !options.foreground && std::io::stdin().is_terminal() => {
+            command.stdin(Stdio::null());
+        }
+        None => {}
+    }
+
+    let spawned = command.args(args.clone()).spawn();
+    // closes our copies of the pty slaves, reading the masters ends with the task
+    drop(command);
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => {
+            // a failure before exec is the sandbox's
+            if let Some(step) = sandbox_report.as_ref().and_then(Report::failed_step) {
+                let outcome = Outcome::SandboxFailed(format!(
+                    "Cannot set up the sandbox for '{}': {}: {}",
+                    path, step, e
+                ));
+                return result(outcome, String::new(), String::new());
+            }
+            let mut err_info = format!(
+                "Spawn failed in '{}'. Cmd: {:?}, Args: {:?}",
+                path, cmd, args,
+            );
+            if !options.in_repos {
+                err_info = format!("Spawn failed (--files): Cmd: {:?}, Args: {:?}", cmd, args);
+            }
+            let outcome = Outcome::SpawnFailed(format!("{}: {}", err_info, e));
+            return result(outcome, String::new(), String::new());
+        }
+    };
+
+    let cpu_usage = options.limits.cpu.map(|_| CpuUsage::watch(child.id()));
+    let target = interrupt::target(child.id() as i32, !options.foreground);
+    interrupt::register(target);
+    if interrupt::stop_requested() {
+        // the signal was forwarded before we registered
+        interrupt::kill(target, libc::SIGTERM);
+    }
+
+    if let Some(mut child_stdin) = child.stdin.take()
+        && let Some(source) = options.stdin.clone()
+    {
+        // the task may exit without reading everything, that is fine
+        smol::spawn(async move {
+            match source.as_ref() {
+                StdinSource::Buffer(buf) => {
+                    let _ = child_stdin.write_all(buf).await;
+                }
+                StdinSource::Spooled(_) => {
+                    let mut offset = 0;
+                    loop {
+                        let spool = source.clone();
+                        let Ok(chunk) = smol::unblock(move || spool.chunk_at(offset)).await else {
+                            break;
+                        };
+                        if chunk.is_empty() || child_stdin.write_all(&chunk).await.is_err() {
+                            break;
+                        }
+                        offset += chunk.len() as u64;
+                    }
+                }
+            }
+        })
+        .detach();
+    }
+
+    let (stdout, stderr) = match pty_masters {
+        Some((stdout_master, stderr_master)) => {
+            (Capture::start(stdout_master), Capture::start(stderr_master))
+        }
+        None => (
+            Capture::start(child.stdout.take().unwrap()),
+            Capture::start(child.stderr.take().unwrap()),
+        ),
+    };
+
+    let status = if let Some(to) = timeout {
+        child.status().timeout(to).await
+    } else {
+        Some(child.status().await)
+    };
+
+    let outcome = match status {
+        Some(Ok(status)) => {
+            if let Some(code) = status.code() {
+                Outcome::Exited(code)
+            } else {
+                let signal = status.signal().unwrap_or_default();
+                let cpu_exceeded = cpu_usage
+                    .as_ref()
+                    .zip(options.limits.cpu)
+                    .is_some_and(|(usage, limit)| usage.exceeded(signal, limit));
+                match limits::exceeded_by_signal(signal) {
+                    Some(reason) => Outcome::LimitExceeded(signal, reason),
+                    None if cpu_exceeded => {
+                        Outcome::LimitExceeded(signal, "CPU time limit exceeded")
+                    }
+                    None => Outcome::Signaled(signal),
+                }
+            }
+        }
+        Some(Err(e)) => Outcome::WaitFailed(format!("Wait failed for '{}': {}", path, e)),
+        None => {
+            // Kill the process and whatever it started (best effort)
+            interrupt::kill(target, libc::SIGKILL);
+            let _ = child.kill();
+            Outcome::TimedOut(timeout.unwrap_or_default())
+        }
+    };
+
+    interrupt::unregister(target);
+
+    let (stdout_str, stderr_str) = zip(stdout.finish(), stderr.finish()).await;
+    result(outcome, stdout_str, stderr_str)
+}
+
+type PtyPair = (smol::Async<std::fs::File>, std::os::fd::OwnedFd);
+
+fn zip_ptys(size: PtySize) -> std::io::Result<(PtyPair, PtyPair)> {
+    Ok((pty::open(size)?, pty::open(size)?))
+}
@@ -0,0 +1,251 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use event_listener::Event;
+use futures_lite::FutureExt;
+use smol::Timer;
+use smol::channel::Receiver;
+
+use crate::debug;
+use crate::pool::{JOB_BACKLOG, Job};
+
+// Spaces out spawns so that no more than one task starts every `min_gap`.
+pub struct StartGate {
+    min_gap: Duration,
+    next_start: smol::lock::Mutex<Option<Instant>>,
+}
+
+impl StartGate {
+    pub fn new(min_gap: Duration) -> StartGate {
+        StartGate {
+            min_gap,
+            next_start: smol::lock::Mutex::new(None),
+        }
+    }
+
+    pub async fn wait(&self) {
+        if self.min_gap.is_zero() {
+            return;
+        }
+        // holding the lock while sleeping queues up everyone else behind us
+        let mut next_start = self.next_start.lock().await;
+        let mut now = Instant::now();
+        if let Some(next) = *next_start
+            && next > now
+        {
+            Timer::at(next).await;
+            now = next;
+        }
+        *next_start = Some(now + self.min_gap);
+    }
+}
+
+// share of time (in percent, over 10s) at least one task stalled on CPU, I/O
+// or memory
+const MAX_PRESSURE: f64 = 10.0;
+const LOAD_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+fn load_average() -> Option<f64> {
+    let loadavg = fs::read_to_string("/proc/loadavg").ok()?;
+    loadavg.split_whitespace().next()?.parse().ok()
+}
+
+// `some avg10=1.23 avg60=...` of /proc/pressure/<resource>
+fn resource_pressure(resource: &str) -> Option<f64> {
+    let pressure = fs::read_to_string(format!("/proc/pressure/{}", resource)).ok()?;
+    let some = pressure.lines().find(|line| line.starts_with("some"))?;
+    let avg10 = some
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("avg10="))?;
+    avg10.parse().ok()
+}
+
+// The worst of CPU, I/O and memory pressure, `None` without PSI (Linux 4.20+).
+pub fn pressure() -> Option<f64> {
+    ["cpu", "io", "memory"]
+        .into_iter()
+        .filter_map(resource_pressure)
+        .reduce(f64::max)
+}
+
+// Holds back new tasks while the machine is saturated (--max-load).
+pub struct LoadGate {
+    max_load: f64,
+    running: Arc<AtomicUsize>,
+}
+
+pub struct LoadSlot {
+    running: Arc<AtomicUsize>,
+}
+
+impl Drop for LoadSlot {
+    fn drop(&mut self) {
+        self.running.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl LoadGate {
+    pub fn new(max_load: f64) -> LoadGate {
+        LoadGate {
+            max_load,
+            running: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn is_saturated(&self) -> bool {
+        if let Some(load) = load_average()
+            && load > self.max_load
+        {
+            debug!("load average {} above {}", load, self.max_load);
+            return true;
+        }
+        if let Some(pressure) = pressure()
+            && pressure > MAX_PRESSURE
+        {
+            debug!("pressure {} above {}", pressure, MAX_PRESSURE);
+            return true;
+        }
+        false
+    }
+
+    // One of our tasks may always run, otherwise a machine kept busy by
+    // someone else would stall us forever.
+    pub async fn wait(&self) -> LoadSlot {
+        while self.running.load(Ordering::SeqCst) > 0 && self.is_saturated() {
+            Timer::after(LOAD_POLL_INTERVAL).await;
+        }
+        self.running.fetch_add(1, Ordering::SeqCst);
+        LoadSlot {
+            running: self.running.clone(),
+        }
+    }
+}
+
+// `https://user@github.com/a/b.git`, `ssh://git@host:22/a/b` and `git@host:a/b` -> host
+pub fn host_of_url(url: &str) -> Option<String> {
+    let rest = if let Some((_, rest)) = url.split_once("://") {
+        rest
+    } else if url.contains('@') && url.contains(':') {
+        url
+    } else {
+        // local path or something we do not understand
+        return None;
+    };
+    let rest = rest.split('/').next().unwrap_or(rest);
+    let rest = rest.rsplit_once('@').map(|(_, host)| host).unwrap_or(rest);
+    let host = rest.split(':').next().unwrap_or(rest);
+    if host.is_empty() {
+        return None;
+    }
+    Some(host.to_lowercase())
+}
+
+pub fn remote_host(path: &str) -> Option<String> {
+    let output = Command::new("git")
+        .args(["-C", path, "remote", "get-url", "origin"])
+        .output()
+        .ok()?;
+    let url = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || url.is_empty() {
+        return None;
+    }
+    host_of_url(&url)
+}
+
+// Limits how many tasks run against the same git host at the same time.
+// Jobs whose host is busy wait in `pending` while later jobs may go ahead,
+// as many as the producer may run ahead of the workers.
+pub struct HostLimits {
+    limit: usize,
+    pending: Mutex<VecDeque<Job>>,
+    running: Mutex<HashMap<String, usize>>,
+    freed: Event,
+}
+
+pub struct HostSlot {
+    limits: Arc<HostLimits>,
+    host: String,
+}
+
+impl Drop for HostSlot {
+    fn drop(&mut self) {
+        let mut running = self.limits.running.lock().unwrap();
+        if let Some(count) = running.get_mut(&self.host) {
+            *count -= 1;
+        }
+        drop(running);
+        self.limits.freed.notify(usize::MAX);
+    }
+}
+
+impl HostLimits {
+    pub fn new(limit: usize) -> HostLimits {
+        HostLimits {
+            limit,
+            pending: Mutex::new(VecDeque::new()),
+            running: Mutex::new(HashMap::new()),
+            freed: Event::new(),
+        }
+    }
+
+    // Takes the first pending job whose host still has room. Jobs without a
+    // remote are never held back.
+    fn take(self: &Arc<Self>) -> Option<(Job, Option<HostSlot>)> {
+        let mut pending = self.pending.lock().unwrap();
+        let mut running = self.running.lock().unwrap();
+        for idx in 0..pending.len() {
+            let Some(host) = pending[idx].host.clone() else {
+                return Some((pending.remove(idx).unwrap(), None));
+            };
+            let count = running.entry(host.clone()).or_insert(0);
+            if *count < self.limit {
+                *count += 1;
+                let slot = HostSlot {
+                    limits: self.clone(),
+                    host,
+                };
+                return Some((pending.remove(idx).unwrap(), Some(slot)));
+            }
+        }
+        None
+    }
+
+    pub async fn next_job(
+        self: &Arc<Self>,
+        jobs: &Receiver<Job>,
+    ) -> Option<(Job, Option<HostSlot>)> {
+        loop {
+            // listen first, a slot freed between `take` and `await` would be lost otherwise
+            let listener = self.freed.listen();
+            if let Some(next) = self.take() {
+                return Some(next);
+            }
+            let pending = self.pending.lock().unwrap().len();
+            if jobs.is_closed() && jobs.is_empty() {
+                if pending == 0 {
+                    return None;
+                }
+                // only jobs for busy hosts are left
+                listener.await;
+                continue;
+            }
+            // full of jobs for busy hosts, the producer waits with the rest
+            if pending >= JOB_BACKLOG {
+                listener.await;
+                continue;
+            }
+            let more = async { jobs.recv().await.ok() };
+            let freed = async {
+                listener.await;
+                None
+            };
+            if let Some(job) = more.or(freed).await {
+                self.pending.lock().unwrap().push_back(job);
+            }
+        }
+    }
+}
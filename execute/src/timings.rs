@@ -0,0 +1,115 @@
+use std::time::{Duration, Instant};
+
+use crate::log_info;
+use crate::task::TaskResult;
+
+const HISTOGRAM_WIDTH: usize = 40;
+
+// upper bounds of the histogram buckets, the last bucket is open
+const BUCKETS: [(Duration, &str); 4] = [
+    (Duration::from_millis(100), "< 100ms"),
+    (Duration::from_secs(1), "100ms - 1s"),
+    (Duration::from_secs(10), "1s - 10s"),
+    (Duration::from_secs(60), "10s - 1m"),
+];
+
+// `4.2s`, or `3m07s` from a minute on
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64();
+    if seconds < 60.0 {
+        return format!("{:.1}s", seconds);
+    }
+    format!(
+        "{}m{:02}s",
+        duration.as_secs() / 60,
+        duration.as_secs() % 60
+    )
+}
+
+struct Entry {
+    path: String,
+    waited: Duration,
+    ran: Duration,
+}
+
+pub struct Timings {
+    start: Instant,
+    entries: Vec<Entry>,
+}
+
+impl Timings {
+    pub fn new() -> Timings {
+        Timings {
+            start: Instant::now(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, result: &TaskResult) {
+        self.entries.push(Entry {
+            path: result.label(),
+            waited: result.waited,
+            ran: result.duration,
+        });
+    }
+
+    pub fn print(&mut self, workers: usize, top: usize) {
+        let total = self.start.elapsed();
+        if self.entries.is_empty() {
+            log_info!("total wall time: {:.2?}", total);
+            return;
+        }
+
+        // how long a task waited says more about its place in the queue
+        self.entries
+            .sort_by_key(|entry| std::cmp::Reverse(entry.ran));
+        log_info!("slowest tasks:");
+        eprintln!("  {:>10} {:>10} path", "ran", "waited");
+        for entry in self.entries.iter().take(top) {
+            eprintln!(
+                "  {:>10.2?} {:>10.2?} {}",
+                entry.ran, entry.waited, entry.path
+            );
+        }
+
+        // how busy the workers were: 100% means no slot was ever idle
+        let busy: Duration = self.entries.iter().map(|entry| entry.ran).sum();
+        let capacity = total.as_secs_f64() * workers as f64;
+        let mut efficiency = 0.0;
+        if capacity > 0.0 {
+            efficiency = busy.as_secs_f64() / capacity * 100.0;
+        }
+        log_info!("total wall time: {:.2?}", total);
+        log_info!(
+            "sum of task times: {:.2?}, parallelism efficiency: {:.0}% of {} workers",
+            busy,
+            efficiency,
+            workers
+        );
+
+        let mut counts = [0usize; BUCKETS.len() + 1];
+        for entry in &self.entries {
+            let idx = BUCKETS
+                .iter()
+                .position(|(bound, _)| entry.ran < *bound)
+                .unwrap_or(BUCKETS.len());
+            counts[idx] += 1;
+        }
+        let max_count = *counts.iter().max().unwrap_or(&1);
+        log_info!("histogram of task times:");
+        for (idx, count) in counts.iter().enumerate() {
+            let mut label = ">= 1m";
+            if idx < BUCKETS.len() {
+                label = BUCKETS[idx].1;
+            }
+            let bar = "#".repeat(count * HISTOGRAM_WIDTH / max_count.max(1));
+            eprintln!(
+                "  {:>10} |{:<width$} {}",
+                label,
+                bar,
+                count,
+                width = HISTOGRAM_WIDTH
+            );
+        }
+    }
+}
@@ -0,0 +1,584 @@
+use std::io::{self, IsTerminal};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{Event as TermEvent, EventStream, KeyCode, KeyEventKind, KeyModifiers};
+use futures_lite::FutureExt;
+use futures_lite::stream::StreamExt;
+use ratatui::DefaultTerminal;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use smol::channel::Receiver;
+
+use crate::matrix;
+use crate::pool::{Event, Job};
+use crate::task::{Outcome, TaskResult};
+use crate::timings::format_duration;
+
+// how often running durations are redrawn
+const TICK: Duration = Duration::from_millis(250);
+// lines PgUp/PgDn move the output pane
+const PAGE: u16 = 10;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+    Queued,
+    Running,
+    Ok,
+    Failed,
+    TimedOut,
+}
+
+impl Status {
+    fn icon(self) -> &'static str {
+        match self {
+            Status::Queued => "·",
+            Status::Running => "▸",
+            Status::Ok => "✓",
+            Status::Failed => "✗",
+            Status::TimedOut => "◷",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Status::Queued => "queued",
+            Status::Running => "running",
+            Status::Ok => "ok",
+            Status::Failed => "failed",
+            Status::TimedOut => "timeout",
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            Status::Queued => Color::DarkGray,
+            Status::Running => Color::Cyan,
+            Status::Ok => Color::Green,
+            Status::Failed => Color::Red,
+            Status::TimedOut => Color::Yellow,
+        }
+    }
+
+    // `f` cycles through all tasks and then one status at a time
+    fn next_filter(filter: Option<Status>) -> Option<Status> {
+        match filter {
+            None => Some(Status::Queued),
+            Some(Status::Queued) => Some(Status::Running),
+            Some(Status::Running) => Some(Status::Ok),
+            Some(Status::Ok) => Some(Status::Failed),
+            Some(Status::Failed) => Some(Status::TimedOut),
+            Some(Status::TimedOut) => None,
+        }
+    }
+}
+
+enum State {
+    Queued,
+    Running(Instant),
+    Done(Box<TaskResult>),
+}
+
+struct Entry {
+    job: Job,
+    state: State,
+    // output of the last result, cleaned up for the terminal
+    lines: Vec<String>,
+}
+
+impl Entry {
+    fn status(&self) -> Status {
+        match &self.state {
+            State::Queued => Status::Queued,
+            State::Running(_) => Status::Running,
+            State::Done(result) => match result.outcome {
+                Outcome::Exited(0) => Status::Ok,
+                Outcome::TimedOut(_) => Status::TimedOut,
+                _ => Status::Failed,
+            },
+        }
+    }
+
+    fn label(&self) -> String {
+        let paths = &self.job.paths;
+        let params = matrix::label(&self.job.params);
+        if paths.len() > 1 {
+            return format!("{} (+{} more){}", paths[0], paths.len() - 1, params);
+        }
+        format!("{}{}", paths[0], params)
+    }
+
+    fn duration(&self) -> String {
+        match &self.state {
+            State::Queued => String::new(),
+            State::Running(started) => format_duration(started.elapsed()),
+            State::Done(result) => format_duration(result.duration),
+        }
+    }
+
+    fn matches(&self, search: &str) -> bool {
+        search.is_empty()
+            || self.job.paths.iter().any(|path| path.contains(search))
+            || self.lines.iter().any(|line| line.contains(search))
+    }
+}
+
+// What the main loop has to do after a key press.
+pub enum Action {
+    None,
+    Quit,
+    Rerun(Job),
+}
+
+enum Wake {
+    Pool(Option<Event>),
+    Key(Option<io::Result<TermEvent>>),
+    Tick,
+}
+
+pub struct Tui {
+    terminal: DefaultTerminal,
+    command: String,
+    events: Receiver<Event>,
+    events_closed: bool,
+    keys: EventStream,
+    keys_closed: bool,
+    entries: Vec<Entry>,
+    // ids of the entries that pass filter and search
+    visible: Vec<usize>,
+    dirty: bool,
+    selected: Option<usize>,
+    // first visible row of the task list
+    offset: usize,
+    scroll: u16,
+    filter: Option<Status>,
+    search: String,
+    typing: bool,
+    all_done: bool,
+    next_draw: Instant,
+}
+
+impl Tui {
+    pub fn start(command: &[String], events: Receiver<Event>) -> io::Result<Tui> {
+        if !io::stdout().is_terminal() {
+            return Err(io::Error::other("stdout is not a terminal"));
+        }
+        Ok(Tui {
+            terminal: ratatui::try_init()?,
+            command: command.join(" "),
+            events,
+            events_closed: false,
+            keys: EventStream::new(),
+            keys_closed: false,
+            entries: Vec::new(),
+            visible: Vec::new(),
+            dirty: true,
+            selected: None,
+            offset: 0,
+            scroll: 0,
+            filter: None,
+            search: String::new(),
+            typing: false,
+            all_done: false,
+            next_draw: Instant::now(),
+        })
+    }
+
+    // Waits for a key press, a job event or the next redraw.
+    pub async fn next(&mut self) -> Action {
+        let events = &self.events;
+        let events_closed = self.events_closed;
+        let keys = &mut self.keys;
+        let keys_closed = self.keys_closed;
+        let next_draw = self.next_draw;
+
+        let pool_event = async {
+            if events_closed {
+                std::future::pending().await
+            }
+            Wake::Pool(events.recv().await.ok())
+        };
+        let key = async {
+            if keys_closed {
+                std::future::pending().await
+            }
+            Wake::Key(keys.next().await)
+        };
+        let tick = async {
+            smol::Timer::at(next_draw).await;
+            Wake::Tick
+        };
+
+        let wake = pool_event.or(key).or(tick).await;
+        let mut action = Action::None;
+        match wake {
+            Wake::Pool(Some(event)) => self.apply(event),
+            Wake::Pool(None) => self.events_closed = true,
+            Wake::Key(Some(Ok(TermEvent::Key(key)))) if key.kind == KeyEventKind::Press => {
+                action = self.on_key(key.code, key.modifiers);
+                self.draw();
+            }
+            Wake::Key(Some(Ok(TermEvent::Resize(_, _)))) => self.draw(),
+            Wake::Key(Some(_)) => {}
+            Wake::Key(None) => self.keys_closed = true,
+            Wake::Tick => self.draw(),
+        }
+        action
+    }
+
+    // jobs may be queued out of order (--deps), gaps get theirs later
+    fn queued(&mut self, job: Job) {
+        let id = job.id;
+        while self.entries.len() <= id {
+            self.entries.push(Entry {
+                job: job.clone(),
+                state: State::Queued,
+                lines: Vec::new(),
+            });
+        }
+        self.entries[id].job = job;
+    }
+
+    fn apply(&mut self, event: Event) {
+        match event {
+            Event::Queued(job) => self.queued(job),
+            Event::Started(id) => {
+                if let Some(entry) = self.entries.get_mut(id) {
+                    entry.state = State::Running(Instant::now());
+                }
+            }
+        }
+        self.dirty = true;
+    }
+
+    pub fn finished(&mut self, result: TaskResult) {
+        // a result can overtake the events of its job
+        while let Ok(event) = self.events.try_recv() {
+            self.apply(event);
+        }
+        if result.job >= self.entries.len() {
+            self.queued(Job {
+                id: result.job,
+                paths: result.paths.clone(),
+                params: result.params.clone(),
+                options: Default::default(),
+                host: None,
+                queued: Instant::now(),
+            });
+        }
+        let entry = &mut self.entries[result.job];
+        entry.lines = output_lines(&result);
+        entry.state = State::Done(Box::new(result));
+        self.dirty = true;
+    }
+
+    pub fn all_done(&mut self) {
+        self.all_done = true;
+        self.draw();
+    }
+
+    fn on_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> Action {
+        if self.typing {
+            match code {
+                KeyCode::Enter => self.typing = false,
+                KeyCode::Esc => {
+                    self.typing = false;
+                    self.search.clear();
+                }
+                KeyCode::Backspace => {
+                    self.search.pop();
+                }
+                KeyCode::Char(c) => self.search.push(c),
+                _ => {}
+            }
+            self.dirty = true;
+            return Action::None;
+        }
+
+        match code {
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return Action::Quit,
+            KeyCode::Char('q') | KeyCode::Esc => return Action::Quit,
+            KeyCode::Down | KeyCode::Char('j') => self.select_by(1),
+            KeyCode::Up | KeyCode::Char('k') => self.select_by(-1),
+            KeyCode::Home | KeyCode::Char('g') => self.select_by(isize::MIN),
+            KeyCode::End | KeyCode::Char('G') => self.select_by(isize::MAX),
+            KeyCode::PageDown | KeyCode::Char(' ') => {
+                self.scroll = self.scroll.saturating_add(PAGE);
+            }
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(PAGE),
+            KeyCode::Char('f') => {
+                self.filter = Status::next_filter(self.filter);
+                self.dirty = true;
+            }
+            KeyCode::Char('/') => {
+                self.typing = true;
+                self.search.clear();
+                self.dirty = true;
+            }
+            KeyCode::Char('n') => self.scroll_to_match(),
+            KeyCode::Char('r') => {
+                if let Some(id) = self.selected
+                    && let State::Done(_) = self.entries[id].state
+                {
+                    self.entries[id].state = State::Running(Instant::now());
+                    self.dirty = true;
+                    return Action::Rerun(self.entries[id].job.clone());
+                }
+            }
+            _ => {}
+        }
+        Action::None
+    }
+
+    fn select_by(&mut self, delta: isize) {
+        self.refresh();
+        let Some(last) = self.visible.len().checked_sub(1) else {
+            return;
+        };
+        let position = self
+            .selected
+            .and_then(|id| self.visible.iter().position(|&visible| visible == id))
+            .unwrap_or(0);
+        let position = (position as isize)
+            .saturating_add(delta)
+            .clamp(0, last as isize);
+        self.selected = Some(self.visible[position as usize]);
+        self.scroll = 0;
+    }
+
+    // moves the output pane to the next line with the search text
+    fn scroll_to_match(&mut self) {
+        let Some(id) = self.selected else {
+            return;
+        };
+        if self.search.is_empty() {
+            return;
+        }
+        let lines = &self.entries[id].lines;
+        let from = self.scroll as usize + 1;
+        let found = lines[from.min(lines.len())..]
+            .iter()
+            .position(|line| line.contains(&self.search))
+            .map(|i| i + from)
+            .or_else(|| lines.iter().position(|line| line.contains(&self.search)));
+        if let Some(line) = found {
+            self.scroll = line.min(u16::MAX as usize) as u16;
+        }
+    }
+
+    fn refresh(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+        self.visible = (0..self.entries.len())
+            .filter(|&id| {
+                let entry = &self.entries[id];
+                self.filter.is_none_or(|status| entry.status() == status)
+                    && entry.matches(&self.search)
+            })
+            .collect();
+        let still_visible = self
+            .selected
+            .is_some_and(|id| self.visible.binary_search(&id).is_ok());
+        if !still_visible {
+            self.selected = self.visible.first().copied();
+            self.scroll = 0;
+        }
+    }
+
+    fn draw(&mut self) {
+        self.next_draw = Instant::now() + TICK;
+        self.refresh();
+
+        let mut counts = [0usize; 5];
+        for entry in &self.entries {
+            counts[entry.status() as usize] += 1;
+        }
+        let done = counts[Status::Ok as usize]
+            + counts[Status::Failed as usize]
+            + counts[Status::TimedOut as usize];
+        let mut header = format!(
+            " {} | {}/{}{} done, {} running, {} failed, {} timed out",
+            self.command,
+            done,
+            self.entries.len(),
+            if self.all_done { "" } else { "+" },
+            counts[Status::Running as usize],
+            counts[Status::Failed as usize],
+            counts[Status::TimedOut as usize],
+        );
+        if let Some(filter) = self.filter {
+            header.push_str(&format!(" | filter: {}", filter.name()));
+        }
+        if !self.search.is_empty() && !self.typing {
+            header.push_str(&format!(" | search: {}", self.search));
+        }
+        let footer = if self.typing {
+            format!(" /{}_", self.search)
+        } else {
+            " j/k select  PgUp/PgDn scroll  f filter  / search  n next match  r re-run  q quit"
+                .to_string()
+        };
+
+        let entries = &self.entries;
+        let visible = &self.visible;
+        let selected = self.selected;
+        let offset = &mut self.offset;
+        let scroll = self.scroll;
+        let search = &self.search;
+        let _ = self.terminal.draw(|frame| {
+            let [top, body, bottom] = Layout::vertical([
+                Constraint::Length(1),
+                Constraint::Min(0),
+                Constraint::Length(1),
+            ])
+            .areas(frame.area());
+            let [left, right] =
+                Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                    .areas(body);
+
+            frame.render_widget(
+                Paragraph::new(header).style(Style::default().add_modifier(Modifier::REVERSED)),
+                top,
+            );
+            frame.render_widget(Paragraph::new(footer), bottom);
+
+            // only the rows on screen become list items, runs can be huge
+            let rows = left.height.saturating_sub(2) as usize;
+            let position = selected.and_then(|id| visible.iter().position(|&v| v == id));
+            if let Some(position) = position {
+                if position < *offset {
+                    *offset = position;
+                } else if rows > 0 && position >= *offset + rows {
+                    *offset = position + 1 - rows;
+                }
+            }
+            *offset = (*offset).min(visible.len().saturating_sub(rows));
+            let items: Vec<ListItem> = visible
+                .iter()
+                .skip(*offset)
+                .take(rows)
+                .map(|&id| {
+                    let entry = &entries[id];
+                    let status = entry.status();
+                    ListItem::new(format!(
+                        "{} {:>7} {}",
+                        status.icon(),
+                        entry.duration(),
+                        entry.label()
+                    ))
+                    .style(Style::default().fg(status.color()))
+                })
+                .collect();
+            let mut list_state = ListState::default();
+            list_state.select(position.map(|position| position - *offset));
+            frame.render_stateful_widget(
+                List::new(items)
+                    .block(Block::default().borders(Borders::ALL).title(" tasks "))
+                    .highlight_style(Style::default().add_modifier(Modifier::REVERSED)),
+                left,
+                &mut list_state,
+            );
+
+            let (title, lines) = match selected.map(|id| &entries[id]) {
+                Some(entry) => {
+                    let title = format!(" {} ", entry.label());
+                    let lines: Vec<Line> = match &entry.state {
+                        State::Queued => vec![Line::from("queued")],
+                        State::Running(started) if entry.lines.is_empty() => vec![Line::from(
+                            format!("running for {}", format_duration(started.elapsed())),
+                        )],
+                        _ => entry
+                            .lines
+                            .iter()
+                            .map(|line| {
+                                if !search.is_empty() && line.contains(search.as_str()) {
+                                    Line::styled(line.as_str(), Style::default().fg(Color::Yellow))
+                                } else {
+                                    Line::from(line.as_str())
+                                }
+                            })
+                            .collect(),
+                    };
+                    (title, lines)
+                }
+                None => (" output ".to_string(), Vec::new()),
+            };
+            frame.render_widget(
+                Paragraph::new(lines)
+                    .block(Block::default().borders(Borders::ALL).title(title))
+                    .scroll((scroll, 0)),
+                right,
+            );
+        });
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+// Escape sequences and control characters would garble the screen.
+fn clean(text: &str) -> String {
+    let mut cleaned = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI, e.g. colors: up to the final byte
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC, e.g. window titles: up to BEL or ST
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            '\t' => cleaned.push_str("    "),
+            c if c.is_control() => {}
+            c => cleaned.push(c),
+        }
+    }
+    cleaned
+}
+
+fn output_lines(result: &TaskResult) -> Vec<String> {
+    let mut lines = vec![format!(
+        "{} ({}) after {}",
+        result.outcome.kind(),
+        match &result.outcome {
+            Outcome::Exited(code) => format!("exit {}", code),
+            Outcome::Signaled(sig) => format!("signal {}", sig),
+            Outcome::LimitExceeded(sig, reason) => format!("signal {}, {}", sig, reason),
+            Outcome::TimedOut(to) => format!("timeout {:?}", to),
+            Outcome::SpawnFailed(err) | Outcome::WaitFailed(err) | Outcome::SandboxFailed(err) => {
+                err.clone()
+            }
+            Outcome::Skipped(reason) => format!("skipped, {}", reason),
+        },
+        format_duration(result.duration)
+    )];
+    if result.paths.len() > 1 {
+        lines.push(format!("paths: {}", result.paths.join(" ")));
+    }
+    lines.push(String::new());
+    lines.extend(result.stdout.lines().map(clean));
+    if !result.stderr.is_empty() {
+        lines.push("[.] stderr:".to_string());
+        lines.extend(result.stderr.lines().map(clean));
+    }
+    lines
+}
@@ -0,0 +1,157 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, IsTerminal};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_signal::Signals;
+use futures_lite::FutureExt;
+use futures_lite::stream::StreamExt;
+use smol::channel::Receiver;
+use smol_timeout::TimeoutExt;
+
+use crate::config::Entry;
+use crate::interrupt;
+use crate::matrix;
+use crate::pool::{self, Pool, Progress};
+use crate::task::TaskResult;
+use crate::{log_info, print_result};
+
+#[cfg(target_os = "linux")]
+use crate::inotify::start;
+
+// how long after its task a repo's own writes may still come in
+const SETTLE: Duration = Duration::from_millis(200);
+
+// --watch is refused before it gets here
+#[cfg(not(target_os = "linux"))]
+fn start(_repos: &[String]) -> io::Result<Receiver<String>> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+// the latest result of every repo, in config order
+fn show_latest(repos: &[String], latest: &HashMap<String, Box<TaskResult>>, show_header: bool) {
+    if io::stdout().is_terminal() {
+        print!("\x1b[H\x1b[2J");
+    }
+    for repo in repos {
+        if let Some(result) = latest.get(repo) {
+            print_result(result, show_header);
+        }
+    }
+}
+
+enum Wake {
+    Changed(Option<String>),
+    Result(Option<Option<Box<TaskResult>>>),
+    Interrupted(i32),
+}
+
+// Re-runs the command in every repo that changed, once `debounce` passed
+// without further changes. Returns the signal that ended it.
+pub async fn run(
+    pool: &Arc<Pool>,
+    entries: Vec<Entry>,
+    mut latest: HashMap<String, Box<TaskResult>>,
+    debounce: Duration,
+    show_header: bool,
+    mut signals: Option<&mut Signals>,
+) -> Result<i32, String> {
+    let repos: Vec<String> = entries.iter().map(|entry| entry.path.clone()).collect();
+    let changes = start(&repos).map_err(|e| format!("cannot watch for changes: {}", e))?;
+    show_latest(&repos, &latest, show_header);
+    log_info!("watching {} repos for changes", repos.len());
+
+    let mut changed: HashSet<String> = HashSet::new();
+    let mut results: Option<Receiver<Option<TaskResult>>> = None;
+    // repos whose changes are the task's own: until it ends, then until the
+    // events it caused are read
+    let mut own_writes: HashMap<String, Option<Instant>> = HashMap::new();
+    loop {
+        let next_change = async {
+            // a burst of changes is handled as one
+            if !changed.is_empty() && results.is_none() {
+                return match changes.recv().timeout(debounce).await {
+                    Some(change) => Wake::Changed(change.ok()),
+                    None => Wake::Changed(None),
+                };
+            }
+            Wake::Changed(changes.recv().await.ok())
+        };
+        let next_result = async {
+            match results.as_ref() {
+                Some(results) => {
+                    Wake::Result(results.recv().await.ok().map(|result| result.map(Box::new)))
+                }
+                None => std::future::pending().await,
+            }
+        };
+        let next_signal = async {
+            if let Some(signals) = signals.as_mut()
+                && let Some(Ok(signal)) = signals.next().await
+            {
+                return Wake::Interrupted(signal as i32);
+            }
+            std::future::pending().await
+        };
+
+        match next_change.or(next_result).or(next_signal).await {
+            // changes in other repos wait for the run to finish
+            Wake::Changed(Some(repo)) => {
+                let own = own_writes
+                    .get(&repo)
+                    .is_some_and(|until| until.is_none_or(|until| Instant::now() < until));
+                if !own {
+                    changed.insert(repo);
+                }
+            }
+            Wake::Changed(None) => {
+                if changed.is_empty() {
+                    return Err("stopped watching for changes".to_string());
+                }
+                // quiet for long enough
+                let changed_entries: Vec<Entry> = entries
+                    .iter()
+                    .filter(|entry| changed.contains(&entry.path))
+                    .cloned()
+                    .collect();
+                changed.clear();
+                own_writes = changed_entries
+                    .iter()
+                    .map(|entry| (entry.path.clone(), None))
+                    .collect();
+                let paths: Vec<&str> = changed_entries
+                    .iter()
+                    .map(|entry| entry.path.as_str())
+                    .collect();
+                log_info!("changed: {}", paths.join(" "));
+                let jobs = pool::produce(
+                    changed_entries.into_iter(),
+                    matrix::combinations(&[]),
+                    None,
+                    pool.hosts.is_some(),
+                    Arc::new(Progress::default()),
+                    None,
+                );
+                results = Some(pool.start(jobs));
+            }
+            Wake::Result(Some(Some(result))) => {
+                own_writes.insert(result.path.clone(), Some(Instant::now() + SETTLE));
+                latest.insert(result.path.clone(), result);
+                show_latest(&repos, &latest, show_header);
+            }
+            Wake::Result(Some(None)) => {}
+            Wake::Result(None) => {
+                results = None;
+                for until in own_writes.values_mut() {
+                    until.get_or_insert(Instant::now() + SETTLE);
+                }
+                log_info!("watching {} repos for changes", repos.len());
+            }
+            Wake::Interrupted(signal) => {
+                interrupt::request_stop();
+                interrupt::signal_all(signal);
+                return Ok(signal);
+            }
+        }
+    }
+}